@@ -0,0 +1,12 @@
+-- Paste bodies stored once per SHA-256 content hash, shared by reference
+CREATE TABLE IF NOT EXISTS paste_contents (
+    hash TEXT PRIMARY KEY,
+    content TEXT NOT NULL,
+    ref_count INTEGER NOT NULL DEFAULT 0
+);
+
+-- Pastes point at their body by hash; pastes.content is left empty
+ALTER TABLE pastes ADD COLUMN content_hash TEXT REFERENCES paste_contents(hash);
+
+-- Index for faster reference lookups
+CREATE INDEX IF NOT EXISTS idx_pastes_content_hash ON pastes(content_hash);
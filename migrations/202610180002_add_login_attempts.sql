@@ -0,0 +1,17 @@
+-- Sign-in history, used for account lockout and shown on the dashboard
+CREATE TABLE IF NOT EXISTS login_attempts (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    username TEXT NOT NULL,
+    user_id INTEGER REFERENCES users(id),
+    ip TEXT,
+    user_agent TEXT,
+    success INTEGER NOT NULL,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Temporary lockout state with exponential backoff
+ALTER TABLE users ADD COLUMN locked_until DATETIME;
+ALTER TABLE users ADD COLUMN lockout_count INTEGER NOT NULL DEFAULT 0;
+
+-- Index for per-user history and failure counts
+CREATE INDEX IF NOT EXISTS idx_login_attempts_user_id ON login_attempts(user_id, created_at);
@@ -0,0 +1,15 @@
+-- Verified email address; only set once the confirmation link is opened
+ALTER TABLE users ADD COLUMN email TEXT;
+
+-- Single-use links sent by email (`verify` and `reset`), stored hashed
+CREATE TABLE IF NOT EXISTS email_tokens (
+    token_hash TEXT PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users(id),
+    purpose TEXT NOT NULL,
+    email TEXT,
+    expires_at DATETIME NOT NULL,
+    used_at DATETIME
+);
+
+CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email ON users(email);
+CREATE INDEX IF NOT EXISTS idx_email_tokens_user_id ON email_tokens(user_id);
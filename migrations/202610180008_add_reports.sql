@@ -0,0 +1,17 @@
+-- NULL, `review` (auto-hidden from /public after reports) or `moderator` (taken down)
+ALTER TABLE pastes ADD COLUMN hidden TEXT;
+
+-- Abuse reports from visitors, open until an admin resolves them
+CREATE TABLE IF NOT EXISTS reports (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    paste_id TEXT NOT NULL,
+    reason TEXT NOT NULL,
+    details TEXT,
+    reporter_id INTEGER,
+    reporter_ip TEXT,
+    created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    resolved_at DATETIME,
+    resolution TEXT
+);
+
+CREATE INDEX IF NOT EXISTS idx_reports_paste_id ON reports(paste_id, resolved_at);
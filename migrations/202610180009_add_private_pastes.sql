@@ -0,0 +1,2 @@
+-- Private pastes can only be opened by their owner (and admins)
+ALTER TABLE pastes ADD COLUMN private INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1,97 @@
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use sha2::{Digest, Sha256};
+
+/// Files under `static/` compiled into the binary. Stylesheets come after the
+/// fonts they reference so their `url()`s can be rewritten to hashed names.
+const FILES: &[(&str, &[u8])] = &[
+    ("fonts/open-sans-400.woff2", include_bytes!("../static/fonts/open-sans-400.woff2")),
+    ("fonts/open-sans-600.woff2", include_bytes!("../static/fonts/open-sans-600.woff2")),
+    ("fonts/source-code-pro-500.woff2", include_bytes!("../static/fonts/source-code-pro-500.woff2")),
+    ("highlight.min.js", include_bytes!("../static/highlight.min.js")),
+    ("highlight.css", include_bytes!("../static/highlight.css")),
+    ("app.css", include_bytes!("../static/app.css")),
+];
+
+/// Hex digits of the content hash put into file names
+const HASH_LEN: usize = 10;
+
+pub struct Asset {
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+struct Assets {
+    /// Served files by hashed path, e.g. `app.3f2a9c1b0e.css`
+    files: HashMap<String, Asset>,
+    /// URL of each file by its name under `static/`
+    urls: HashMap<&'static str, String>,
+}
+
+impl Assets {
+    fn build() -> Self {
+        let mut files = HashMap::new();
+        let mut urls: HashMap<&'static str, String> = HashMap::new();
+
+        for &(name, bytes) in FILES {
+            let mut body = bytes.to_vec();
+            if name.ends_with(".css") {
+                let mut css = String::from_utf8_lossy(&body).into_owned();
+                for (dep, url) in &urls {
+                    css = css.replace(&format!("url('{}')", dep), &format!("url('{}')", url));
+                }
+                body = css.into_bytes();
+            }
+
+            let hashed = hashed_name(name, &body);
+            urls.insert(name, format!("/static/{}", hashed));
+            files.insert(
+                hashed,
+                Asset {
+                    content_type: content_type(name),
+                    body,
+                },
+            );
+        }
+
+        Self { files, urls }
+    }
+}
+
+fn assets() -> &'static Assets {
+    static ASSETS: OnceLock<Assets> = OnceLock::new();
+    ASSETS.get_or_init(Assets::build)
+}
+
+/// `dir/name.ext` becomes `dir/name.<hash>.ext`
+fn hashed_name(name: &str, body: &[u8]) -> String {
+    let digest = format!("{:x}", Sha256::digest(body));
+    let hash = &digest[..HASH_LEN];
+    match name.rsplit_once('.') {
+        Some((stem, ext)) => format!("{}.{}.{}", stem, hash, ext),
+        None => format!("{}.{}", name, hash),
+    }
+}
+
+fn content_type(name: &str) -> &'static str {
+    match name.rsplit_once('.').map(|(_, ext)| ext) {
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") => "text/javascript; charset=utf-8",
+        Some("woff2") => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// Versioned URL of a file under `static/`, for use in templates
+pub fn url(name: &str) -> String {
+    match assets().urls.get(name) {
+        Some(url) => url.clone(),
+        None => format!("/static/{}", name),
+    }
+}
+
+/// A file by its hashed path under `/static`
+pub fn get(path: &str) -> Option<&'static Asset> {
+    assets().files.get(path)
+}
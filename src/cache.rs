@@ -0,0 +1,230 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicI64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use askama::{Html, MarkupDisplay};
+use chrono::NaiveDateTime;
+use lru::LruCache;
+use sqlx::SqlitePool;
+
+use crate::config::CacheConfig;
+use crate::db;
+use crate::models::Paste;
+
+// =============================================================================
+// Entries
+// =============================================================================
+
+/// A paste as kept in memory, with its body already escaped for the page
+pub struct CachedPaste {
+    /// `view_count` is as loaded; see `views` for the current count
+    pub paste: Paste,
+    /// `paste.content` escaped for HTML
+    pub code_html: String,
+    pub lines: usize,
+    views: AtomicI64,
+}
+
+impl CachedPaste {
+    fn new(paste: Paste, unflushed_views: i64) -> Self {
+        let code_html = MarkupDisplay::new_unsafe(&paste.content, Html).to_string();
+        let lines = paste.content.lines().count();
+        let views = AtomicI64::new(i64::from(paste.view_count) + unflushed_views);
+        Self {
+            paste,
+            code_html,
+            lines,
+            views,
+        }
+    }
+
+    /// Views so far, including those not written to the database yet
+    pub fn views(&self) -> i64 {
+        self.views.load(Ordering::Relaxed)
+    }
+
+    fn expired(&self) -> bool {
+        self.paste.expires_at.is_some_and(|e| e < chrono::Utc::now().naive_utc())
+    }
+
+    fn size(&self) -> usize {
+        self.paste.content.len() + self.code_html.len()
+    }
+
+    fn key(&self) -> ViewKey {
+        (self.paste.id.clone(), self.paste.created_at)
+    }
+}
+
+struct Entries {
+    lru: LruCache<String, Arc<CachedPaste>>,
+    bytes: usize,
+    /// Bumped on every invalidation, so a lookup that raced one doesn't put
+    /// the old paste back
+    generation: u64,
+    /// Set while view counts are being written. A paste read from the
+    /// database then may or may not include them, so it isn't cached.
+    flushing: bool,
+}
+
+/// Paste ID and creation time, so counts for a deleted paste never land on a
+/// later one that reuses its ID
+pub type ViewKey = (String, NaiveDateTime);
+
+#[derive(Default)]
+struct Views {
+    pending: HashMap<ViewKey, i64>,
+    /// Taken by a flush that hasn't committed yet
+    in_flight: HashMap<ViewKey, i64>,
+}
+
+impl Views {
+    fn unflushed(&self, key: &ViewKey) -> i64 {
+        self.pending.get(key).copied().unwrap_or(0) + self.in_flight.get(key).copied().unwrap_or(0)
+    }
+}
+
+// =============================================================================
+// Cache
+// =============================================================================
+
+/// Recently viewed pastes, bounded by count and size, plus the views they got
+/// since the last write to the database. Anything that changes or deletes a
+/// paste has to call `remove` (or `clear`) so the next view sees it.
+pub struct PasteCache {
+    config: CacheConfig,
+    entries: Mutex<Entries>,
+    /// Views not yet written to the database
+    views: Mutex<Views>,
+}
+
+impl PasteCache {
+    pub fn new(config: CacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(Entries {
+                lru: LruCache::unbounded(),
+                bytes: 0,
+                generation: 0,
+                flushing: false,
+            }),
+            views: Mutex::new(Views::default()),
+        }
+    }
+
+    /// The paste with this ID, from memory when possible. Expired pastes are
+    /// treated as missing and dropped; the cleanup task deletes their rows.
+    pub async fn get(&self, pool: &SqlitePool, id: &str) -> Result<Option<Arc<CachedPaste>>, sqlx::Error> {
+        let generation = {
+            let mut entries = self.entries.lock().unwrap();
+            match entries.lru.get(id) {
+                Some(entry) if entry.expired() => {
+                    drop(entries);
+                    self.delete(id);
+                    return Ok(None);
+                }
+                Some(entry) => return Ok(Some(entry.clone())),
+                None => entries.generation,
+            }
+        };
+
+        let Some(paste) = db::fetch_paste(pool, id).await? else {
+            return Ok(None);
+        };
+        let unflushed = self.views.lock().unwrap().unflushed(&(paste.id.clone(), paste.created_at));
+        let entry = Arc::new(CachedPaste::new(paste, unflushed));
+        if entry.expired() {
+            return Ok(None);
+        }
+        self.insert(entry.clone(), generation);
+        Ok(Some(entry))
+    }
+
+    fn insert(&self, entry: Arc<CachedPaste>, generation: u64) {
+        let size = entry.size();
+        if self.config.max_entries == 0 || size > self.config.max_bytes {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.generation != generation || entries.flushing {
+            return;
+        }
+        if let Some(old) = entries.lru.put(entry.paste.id.clone(), entry) {
+            entries.bytes -= old.size();
+        }
+        entries.bytes += size;
+        while entries.lru.len() > self.config.max_entries || entries.bytes > self.config.max_bytes {
+            match entries.lru.pop_lru() {
+                Some((_, evicted)) => entries.bytes -= evicted.size(),
+                None => break,
+            }
+        }
+    }
+
+    /// Forget a paste that was changed or deleted
+    pub fn remove(&self, id: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(old) = entries.lru.pop(id) {
+            entries.bytes -= old.size();
+        }
+        entries.generation += 1;
+    }
+
+    /// Forget a deleted paste along with the views it got that weren't written
+    pub fn delete(&self, id: &str) {
+        self.remove(id);
+        let mut views = self.views.lock().unwrap();
+        views.pending.retain(|(paste, _), _| paste != id);
+        views.in_flight.retain(|(paste, _), _| paste != id);
+    }
+
+    /// Forget everything, for changes that touch many pastes at once
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.lru.clear();
+        entries.bytes = 0;
+        entries.generation += 1;
+    }
+
+    /// Count a view now; it reaches the database with the next `flush`
+    pub fn count_view(&self, entry: &CachedPaste) {
+        entry.views.fetch_add(1, Ordering::Relaxed);
+        *self.views.lock().unwrap().pending.entry(entry.key()).or_default() += 1;
+    }
+
+    /// Write the views counted since the last flush in one transaction. They
+    /// stay visible to `get` until committed and are kept for the next try if
+    /// that fails.
+    pub async fn flush(&self, pool: &SqlitePool) -> Result<usize, sqlx::Error> {
+        let writing = {
+            let mut views = self.views.lock().unwrap();
+            if views.pending.is_empty() {
+                return Ok(0);
+            }
+            views.in_flight = std::mem::take(&mut views.pending);
+            views.in_flight.clone()
+        };
+
+        self.set_flushing(true);
+        let result = db::add_views(pool, &writing).await;
+        {
+            let mut views = self.views.lock().unwrap();
+            let in_flight = std::mem::take(&mut views.in_flight);
+            if result.is_err() {
+                for (key, count) in in_flight {
+                    *views.pending.entry(key).or_default() += count;
+                }
+            }
+        }
+        self.set_flushing(false);
+
+        result.map(|()| writing.len())
+    }
+
+    fn set_flushing(&self, flushing: bool) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.flushing = flushing;
+        entries.generation += 1;
+    }
+}
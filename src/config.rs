@@ -0,0 +1,829 @@
+use std::env;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+// =============================================================================
+// Config
+// =============================================================================
+
+/// Runtime settings read from the environment (and `.env`) at startup
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Base URL the site is reached at, used for links in email and SSO redirects
+    pub public_url: String,
+    pub ids: IdConfig,
+    pub limits: LimitConfig,
+    pub rate_limits: RateLimitConfig,
+    pub lockout: LockoutConfig,
+    pub auth: AuthConfig,
+    pub passwords: PasswordConfig,
+    pub moderation: ModerationConfig,
+    pub secrets: SecretConfig,
+    pub spam: SpamConfig,
+    pub security: SecurityConfig,
+    pub listen: ListenConfig,
+    pub logging: LogConfig,
+    pub metrics: MetricsConfig,
+    pub cache: CacheConfig,
+    /// Present when the server terminates TLS itself
+    pub tls: Option<TlsConfig>,
+    pub mail: MailConfig,
+    pub oidc: OidcConfig,
+}
+
+impl Config {
+    pub fn from_env() -> Result<Self, String> {
+        let public_url = non_empty_env("PUBLIC_URL")
+            .unwrap_or_else(|| "http://localhost:3000".to_string())
+            .trim_end_matches('/')
+            .to_string();
+
+        Ok(Self {
+            oidc: OidcConfig::from_env(&public_url)?,
+            tls: TlsConfig::from_env(&public_url)?,
+            listen: ListenConfig::from_env()?,
+            logging: LogConfig::from_env()?,
+            metrics: MetricsConfig::from_env()?,
+            cache: CacheConfig::from_env()?,
+            public_url,
+            ids: IdConfig::from_env()?,
+            limits: LimitConfig::from_env()?,
+            rate_limits: RateLimitConfig::from_env()?,
+            lockout: LockoutConfig::from_env()?,
+            auth: AuthConfig::from_env()?,
+            passwords: PasswordConfig::from_env()?,
+            moderation: ModerationConfig::from_env()?,
+            secrets: SecretConfig::from_env()?,
+            spam: SpamConfig::from_env()?,
+            security: SecurityConfig::from_env()?,
+            mail: MailConfig::from_env()?,
+        })
+    }
+
+    /// Whether visitors reach the site over HTTPS, directly or through a proxy
+    pub fn serves_https(&self) -> bool {
+        self.tls.is_some() || self.public_url.starts_with("https://")
+    }
+}
+
+// =============================================================================
+// Paste IDs
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IdMode {
+    /// Random characters drawn from `alphabet`
+    Random,
+    /// Dictionary words joined by dashes, e.g. `amber-falcon-river`
+    Words,
+}
+
+#[derive(Debug, Clone)]
+pub struct IdConfig {
+    pub mode: IdMode,
+    pub length: usize,
+    pub alphabet: Vec<char>,
+    pub words: usize,
+}
+
+impl IdConfig {
+    fn from_env() -> Result<Self, String> {
+        let mode = match env::var("ID_MODE").unwrap_or_default().to_lowercase().as_str() {
+            "" | "random" => IdMode::Random,
+            "words" => IdMode::Words,
+            other => return Err(format!("ID_MODE must be `random` or `words`, got `{}`", other)),
+        };
+
+        let length: usize = parse_env("ID_LENGTH", 8)?;
+        if !(4..=64).contains(&length) {
+            return Err("ID_LENGTH must be between 4 and 64".to_string());
+        }
+
+        let alphabet: Vec<char> = match env::var("ID_ALPHABET") {
+            Ok(s) if !s.is_empty() => s.chars().collect(),
+            _ => nanoid::alphabet::SAFE.to_vec(),
+        };
+        if alphabet.len() < 2 || alphabet.len() > 255 {
+            return Err("ID_ALPHABET must contain between 2 and 255 characters".to_string());
+        }
+        if !alphabet.iter().all(|c| c.is_ascii_alphanumeric() || *c == '-' || *c == '_') {
+            return Err("ID_ALPHABET may only contain letters, digits, `-` and `_`".to_string());
+        }
+        let mut unique = alphabet.clone();
+        unique.sort_unstable();
+        unique.dedup();
+        if unique.len() != alphabet.len() {
+            return Err("ID_ALPHABET must not repeat characters".to_string());
+        }
+
+        let words: usize = parse_env("ID_WORDS", 3)?;
+        if !(2..=8).contains(&words) {
+            return Err("ID_WORDS must be between 2 and 8".to_string());
+        }
+
+        Ok(Self { mode, length, alphabet, words })
+    }
+}
+
+// =============================================================================
+// Limits
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct LimitConfig {
+    /// Largest request body accepted by any form, in bytes
+    pub max_body_bytes: usize,
+    /// Largest paste content accepted, in bytes
+    pub max_paste_bytes: usize,
+    /// Longest allowed expiration, in days
+    pub max_expiration_days: i64,
+}
+
+impl LimitConfig {
+    fn from_env() -> Result<Self, String> {
+        let max_paste_bytes: usize = parse_env("MAX_PASTE_BYTES", 1024 * 1024)?;
+        // Form encoding can triple the size of the content on the wire
+        let max_body_bytes: usize = parse_env("MAX_BODY_BYTES", max_paste_bytes.saturating_mul(3) + 64 * 1024)?;
+        let max_expiration_days: i64 = parse_env("MAX_EXPIRATION_DAYS", 365)?;
+
+        if max_paste_bytes == 0 || max_body_bytes == 0 {
+            return Err("MAX_PASTE_BYTES and MAX_BODY_BYTES must be greater than 0".to_string());
+        }
+        // A century, which keeps the date arithmetic on expirations far from overflowing
+        if !(1..=36_500).contains(&max_expiration_days) {
+            return Err("MAX_EXPIRATION_DAYS must be between 1 and 36500".to_string());
+        }
+
+        Ok(Self { max_body_bytes, max_paste_bytes, max_expiration_days })
+    }
+}
+
+// =============================================================================
+// Rate Limits
+// =============================================================================
+
+/// `max` requests per `window`, written as e.g. `10/60s`, `5/1m` or `100/1h`
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimit {
+    pub max: u32,
+    pub window: Duration,
+}
+
+impl FromStr for RateLimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (max, period) = s.split_once('/').ok_or("expected `<count>/<period>`")?;
+        let max: u32 = max.trim().parse().map_err(|_| "invalid count")?;
+        let window = parse_duration(period)?;
+        if max == 0 {
+            return Err("count must be greater than 0".to_string());
+        }
+        Ok(Self { max, window })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RateLimitConfig {
+    pub create_paste: Option<RateLimit>,
+    pub login: Option<RateLimit>,
+    pub unlock: Option<RateLimit>,
+    pub report: Option<RateLimit>,
+    /// Number of reverse proxies in front of us whose `X-Forwarded-For`
+    /// entries are trusted; 0 uses the socket peer address
+    pub trusted_proxy_hops: usize,
+    /// Bearer tokens that bypass rate limiting
+    pub exempt_tokens: Vec<String>,
+}
+
+impl RateLimitConfig {
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            create_paste: parse_rate_limit("RATE_LIMIT_CREATE", "20/1m")?,
+            login: parse_rate_limit("RATE_LIMIT_LOGIN", "10/5m")?,
+            unlock: parse_rate_limit("RATE_LIMIT_UNLOCK", "10/5m")?,
+            report: parse_rate_limit("RATE_LIMIT_REPORT", "10/1h")?,
+            trusted_proxy_hops: parse_env("TRUSTED_PROXY_HOPS", 0)?,
+            exempt_tokens: parse_list("RATE_LIMIT_EXEMPT_TOKENS"),
+        })
+    }
+}
+
+/// A rate limit setting; `off` disables the bucket
+fn parse_rate_limit(key: &str, default: &str) -> Result<Option<RateLimit>, String> {
+    let value = env::var(key).unwrap_or_else(|_| default.to_string());
+    let value = value.trim();
+    if value.eq_ignore_ascii_case("off") || value == "0" {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("{} has an invalid value `{}`: {}", key, value, e))
+}
+
+// =============================================================================
+// Account Lockout
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct LockoutConfig {
+    /// Failed logins within `window` that lock the account; 0 disables lockout
+    pub threshold: u32,
+    pub window: Duration,
+    /// Length of the first lockout; each further lockout doubles it
+    pub base: Duration,
+    pub max: Duration,
+}
+
+impl LockoutConfig {
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            threshold: parse_env("LOCKOUT_THRESHOLD", 5)?,
+            window: parse_duration_env("LOCKOUT_WINDOW", "15m")?,
+            base: parse_duration_env("LOCKOUT_DURATION", "5m")?,
+            max: parse_duration_env("LOCKOUT_MAX_DURATION", "24h")?,
+        })
+    }
+}
+
+// =============================================================================
+// Authentication
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistrationMode {
+    /// Anyone can create an account
+    Open,
+    /// Only single sign-on and existing accounts
+    Closed,
+    /// New accounts need an invite code
+    Invite,
+}
+
+#[derive(Debug, Clone)]
+pub struct AuthConfig {
+    /// Every account must enroll in TOTP two-factor authentication
+    pub require_two_factor: bool,
+    /// Accounts that are made administrators when they exist
+    pub admin_usernames: Vec<String>,
+    pub registration: RegistrationMode,
+    /// Case-insensitive globs (`*`, `?`) that new usernames must match one
+    /// of; empty allows any username
+    pub username_patterns: Vec<String>,
+    /// Unused invites a non-admin may hold at once; 0 leaves invites to admins
+    pub invites_per_user: i64,
+}
+
+impl AuthConfig {
+    fn from_env() -> Result<Self, String> {
+        let registration = match env::var("REGISTRATION_MODE").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "open" => RegistrationMode::Open,
+            "closed" => RegistrationMode::Closed,
+            "invite" => RegistrationMode::Invite,
+            other => {
+                return Err(format!("REGISTRATION_MODE must be `open`, `closed` or `invite`, got `{}`", other));
+            }
+        };
+
+        Ok(Self {
+            require_two_factor: parse_bool_env("REQUIRE_2FA", false)?,
+            admin_usernames: parse_list("ADMIN_USERNAMES"),
+            registration,
+            username_patterns: parse_list("USERNAME_PATTERNS"),
+            invites_per_user: parse_env("INVITES_PER_USER", 5)?,
+        })
+    }
+}
+
+// =============================================================================
+// Password Hashing
+// =============================================================================
+
+/// Argon2id cost for new account and paste password hashes. Hashes made with
+/// other settings are upgraded the next time their password is entered.
+#[derive(Debug, Clone)]
+pub struct PasswordConfig {
+    /// Memory per hash in KiB
+    pub memory_kib: u32,
+    pub iterations: u32,
+    pub parallelism: u32,
+}
+
+impl PasswordConfig {
+    fn from_env() -> Result<Self, String> {
+        let config = Self {
+            memory_kib: parse_env("ARGON2_MEMORY_KIB", 19456)?,
+            iterations: parse_env("ARGON2_ITERATIONS", 2)?,
+            parallelism: parse_env("ARGON2_PARALLELISM", 1)?,
+        };
+        argon2::Params::new(config.memory_kib, config.iterations, config.parallelism, None)
+            .map_err(|e| format!("Invalid Argon2 parameters: {}", e))?;
+        Ok(config)
+    }
+}
+
+// =============================================================================
+// Moderation
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct ModerationConfig {
+    /// Open reports after which a paste is hidden from the public list until
+    /// an admin reviews it; 0 never hides automatically
+    pub report_threshold: u32,
+}
+
+impl ModerationConfig {
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            report_threshold: parse_env("REPORT_THRESHOLD", 3)?,
+        })
+    }
+}
+
+// =============================================================================
+// Secret Detection
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecretPolicy {
+    /// Don't scan pastes
+    Off,
+    /// Ask before storing a public paste that looks like it holds secrets
+    Warn,
+    /// Refuse to store such a paste unless it is private or password-protected
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct SecretConfig {
+    pub policy: SecretPolicy,
+    /// Extra patterns, one `name = regex` per line
+    pub patterns_file: Option<String>,
+    /// Shannon entropy (bits per character) at which a token counts as a
+    /// likely secret; 0 turns the heuristic off
+    pub entropy_threshold: f64,
+    /// Shortest token the entropy heuristic looks at
+    pub entropy_min_length: usize,
+}
+
+impl SecretConfig {
+    fn from_env() -> Result<Self, String> {
+        let policy = match env::var("SECRET_POLICY").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "warn" => SecretPolicy::Warn,
+            "block" => SecretPolicy::Block,
+            "off" => SecretPolicy::Off,
+            other => {
+                return Err(format!("SECRET_POLICY must be `off`, `warn` or `block`, got `{}`", other));
+            }
+        };
+
+        Ok(Self {
+            policy,
+            patterns_file: non_empty_env("SECRET_PATTERNS_FILE"),
+            entropy_threshold: parse_env("SECRET_ENTROPY_THRESHOLD", 4.5)?,
+            entropy_min_length: parse_env("SECRET_ENTROPY_MIN_LENGTH", 32)?,
+        })
+    }
+}
+
+// =============================================================================
+// Spam Filtering
+// =============================================================================
+
+/// Checks applied to pastes from visitors who aren't signed in
+#[derive(Debug, Clone)]
+pub struct SpamConfig {
+    /// Most links an anonymous paste may contain; 0 allows any number
+    pub max_links: usize,
+    /// Largest share of an anonymous paste (by non-space characters) that may
+    /// be links; 0 turns the check off
+    pub max_link_ratio: f64,
+    /// Case-insensitive words and phrases that get a paste rejected
+    pub blocklist: Vec<String>,
+    /// More blocklist entries, one per line
+    pub blocklist_file: Option<String>,
+    /// Leading zero bits required of the proof-of-work hash; 0 turns the
+    /// challenge off
+    pub pow_difficulty: u32,
+    /// Show anonymous pastes on `/public`
+    pub list_anonymous: bool,
+}
+
+impl SpamConfig {
+    fn from_env() -> Result<Self, String> {
+        let pow_difficulty = parse_env("SPAM_POW_DIFFICULTY", 0)?;
+        if pow_difficulty > 32 {
+            return Err("SPAM_POW_DIFFICULTY must be between 0 and 32".to_string());
+        }
+        let max_link_ratio: f64 = parse_env("SPAM_MAX_LINK_RATIO", 0.5)?;
+        if !(0.0..=1.0).contains(&max_link_ratio) {
+            return Err("SPAM_MAX_LINK_RATIO must be between 0 and 1".to_string());
+        }
+
+        Ok(Self {
+            max_links: parse_env("SPAM_MAX_LINKS", 10)?,
+            max_link_ratio,
+            blocklist: parse_list("SPAM_BLOCKLIST"),
+            blocklist_file: non_empty_env("SPAM_BLOCKLIST_FILE"),
+            pow_difficulty,
+            list_anonymous: parse_bool_env("PUBLIC_LIST_ANONYMOUS", true)?,
+        })
+    }
+}
+
+// =============================================================================
+// Security Headers
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct SecurityConfig {
+    /// `max-age` of the Strict-Transport-Security header, sent when the site
+    /// is served over HTTPS; 0 leaves the header out
+    pub hsts_max_age: u64,
+    /// Sources allowed to frame our pages, as a CSP source list
+    pub frame_ancestors: String,
+}
+
+impl SecurityConfig {
+    fn from_env() -> Result<Self, String> {
+        let frame_ancestors = non_empty_env("FRAME_ANCESTORS").unwrap_or_else(|| "'none'".to_string());
+        if frame_ancestors.contains([';', ',', '\r', '\n']) {
+            return Err("FRAME_ANCESTORS must be a space-separated list of sources".to_string());
+        }
+
+        Ok(Self {
+            hsts_max_age: parse_env("HSTS_MAX_AGE", 31_536_000)?,
+            frame_ancestors,
+        })
+    }
+}
+
+// =============================================================================
+// Logging
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// One human-readable line per event
+    Text,
+    /// One JSON object per line, for log collectors
+    Json,
+}
+
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// `LOG_LEVEL` as an `EnvFilter` directive, e.g. `warn,oxide_paste::db=debug`
+    pub filter: String,
+    pub format: LogFormat,
+}
+
+impl LogConfig {
+    fn from_env() -> Result<Self, String> {
+        let filter = env::var("LOG_LEVEL").ok().filter(|v| !v.trim().is_empty()).unwrap_or_else(|| "info".to_string());
+
+        let format = match env::var("LOG_FORMAT").unwrap_or_default().trim().to_lowercase().as_str() {
+            "" | "text" => LogFormat::Text,
+            "json" => LogFormat::Json,
+            other => return Err(format!("LOG_FORMAT must be `text` or `json`, got `{}`", other)),
+        };
+
+        Ok(Self { filter, format })
+    }
+}
+
+// =============================================================================
+// Metrics
+// =============================================================================
+
+/// Where `/metrics` is served. With neither setting it isn't served at all.
+#[derive(Debug, Clone, Default)]
+pub struct MetricsConfig {
+    /// Bearer token scrapers must send
+    pub token: Option<String>,
+    /// Separate plain HTTP listener that serves only `/metrics`, instead of
+    /// the main listeners
+    pub addr: Option<SocketAddr>,
+}
+
+impl MetricsConfig {
+    fn from_env() -> Result<Self, String> {
+        let addr = non_empty_env("METRICS_ADDR")
+            .map(|v| v.parse().map_err(|_| format!("METRICS_ADDR has an invalid value: `{}`", v)))
+            .transpose()?;
+        Ok(Self {
+            token: non_empty_env("METRICS_TOKEN"),
+            addr,
+        })
+    }
+
+    /// Served alongside the site itself, which needs a token to keep it private
+    pub fn on_main_listeners(&self) -> bool {
+        self.token.is_some() && self.addr.is_none()
+    }
+}
+
+// =============================================================================
+// Paste Cache
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct CacheConfig {
+    /// Most pastes kept in memory; 0 turns the cache off
+    pub max_entries: usize,
+    /// Most content bytes kept in memory, counting the rendered copy
+    pub max_bytes: usize,
+    /// How often view counts are written to the database
+    pub view_flush_interval: Duration,
+}
+
+impl CacheConfig {
+    fn from_env() -> Result<Self, String> {
+        Ok(Self {
+            max_entries: parse_env("PASTE_CACHE_ENTRIES", 1000)?,
+            max_bytes: parse_env("PASTE_CACHE_BYTES", 64 * 1024 * 1024)?,
+            view_flush_interval: parse_duration_env("VIEW_FLUSH_INTERVAL", "10s")?,
+        })
+    }
+}
+
+// =============================================================================
+// Listeners
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl FromStr for ListenAddr {
+    type Err = String;
+
+    /// `host:port`, `[::]:port` or `unix:/path/to/socket`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(path) = s.strip_prefix("unix:") {
+            if path.is_empty() {
+                return Err("unix: needs a socket path".to_string());
+            }
+            return Ok(Self::Unix(PathBuf::from(path)));
+        }
+        s.parse()
+            .map(Self::Tcp)
+            .map_err(|_| format!("`{}` is neither host:port nor unix:/path", s))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ListenConfig {
+    /// Where to accept connections; ignored when systemd passes sockets in
+    pub addrs: Vec<ListenAddr>,
+    /// Permissions given to Unix sockets we create
+    pub socket_mode: u32,
+    /// How long open connections and background tasks get to finish after
+    /// SIGINT or SIGTERM
+    pub shutdown_timeout: Duration,
+}
+
+impl ListenConfig {
+    fn from_env() -> Result<Self, String> {
+        let mut addrs = parse_list("LISTEN")
+            .iter()
+            .map(|v| v.parse().map_err(|e| format!("LISTEN has an invalid value: {}", e)))
+            .collect::<Result<Vec<ListenAddr>, String>>()?;
+        if addrs.is_empty() {
+            addrs.push(ListenAddr::Tcp(SocketAddr::from(([0, 0, 0, 0], 3000))));
+        }
+
+        let mode = non_empty_env("SOCKET_MODE").unwrap_or_else(|| "660".to_string());
+        let socket_mode = u32::from_str_radix(mode.trim_start_matches("0o"), 8)
+            .ok()
+            .filter(|m| *m <= 0o777)
+            .ok_or_else(|| format!("SOCKET_MODE must be octal permissions such as 660, got `{}`", mode))?;
+
+        Ok(Self {
+            addrs,
+            socket_mode,
+            shutdown_timeout: parse_duration_env("SHUTDOWN_TIMEOUT", "10s")?,
+        })
+    }
+}
+
+// =============================================================================
+// TLS
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_file: String,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_file: String,
+    /// How often the files are checked for changes
+    pub reload_interval: Duration,
+    /// Plain HTTP listener that redirects every request to `PUBLIC_URL`
+    pub redirect_addr: Option<SocketAddr>,
+}
+
+impl TlsConfig {
+    /// `None` unless both `TLS_CERT_FILE` and `TLS_KEY_FILE` are set
+    fn from_env(public_url: &str) -> Result<Option<Self>, String> {
+        let redirect_addr = non_empty_env("HTTP_REDIRECT_ADDR")
+            .map(|v| v.parse().map_err(|_| format!("HTTP_REDIRECT_ADDR has an invalid value: `{}`", v)))
+            .transpose()?;
+
+        let (cert_file, key_file) = match (non_empty_env("TLS_CERT_FILE"), non_empty_env("TLS_KEY_FILE")) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) if redirect_addr.is_some() => {
+                return Err("HTTP_REDIRECT_ADDR needs TLS_CERT_FILE and TLS_KEY_FILE".to_string())
+            }
+            (None, None) => return Ok(None),
+            _ => return Err("TLS_CERT_FILE and TLS_KEY_FILE must be set together".to_string()),
+        };
+        if redirect_addr.is_some() && !public_url.starts_with("https://") {
+            return Err("PUBLIC_URL must start with https:// when HTTP_REDIRECT_ADDR is set".to_string());
+        }
+
+        Ok(Some(Self {
+            cert_file,
+            key_file,
+            reload_interval: parse_duration_env("TLS_RELOAD_INTERVAL", "30s")?,
+            redirect_addr,
+        }))
+    }
+}
+
+// =============================================================================
+// Mail
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SmtpSecurity {
+    /// Plain connection, for local relays and capture servers
+    None,
+    StartTls,
+    /// Implicit TLS from the first byte (usually port 465)
+    Tls,
+}
+
+#[derive(Debug, Clone)]
+pub struct MailConfig {
+    /// SMTP relay; email features are disabled when unset
+    pub smtp_host: Option<String>,
+    /// Defaults to the usual port for `smtp_security`
+    pub smtp_port: Option<u16>,
+    pub smtp_security: SmtpSecurity,
+    pub smtp_username: Option<String>,
+    pub smtp_password: Option<String>,
+    pub from: String,
+    pub reset_token_ttl: Duration,
+}
+
+impl MailConfig {
+    fn from_env() -> Result<Self, String> {
+        let smtp_security = match env::var("SMTP_SECURITY").unwrap_or_default().to_lowercase().as_str() {
+            "" | "starttls" => SmtpSecurity::StartTls,
+            "tls" => SmtpSecurity::Tls,
+            "none" => SmtpSecurity::None,
+            other => {
+                return Err(format!("SMTP_SECURITY must be `none`, `starttls` or `tls`, got `{}`", other));
+            }
+        };
+
+        let smtp_port = match env::var("SMTP_PORT") {
+            Ok(v) if !v.trim().is_empty() => Some(
+                v.trim()
+                    .parse()
+                    .map_err(|_| format!("SMTP_PORT has an invalid value: `{}`", v))?,
+            ),
+            _ => None,
+        };
+
+        Ok(Self {
+            smtp_host: non_empty_env("SMTP_HOST"),
+            smtp_port,
+            smtp_security,
+            smtp_username: non_empty_env("SMTP_USERNAME"),
+            smtp_password: non_empty_env("SMTP_PASSWORD"),
+            from: non_empty_env("MAIL_FROM").unwrap_or_else(|| "OxidePaste <noreply@localhost>".to_string()),
+            reset_token_ttl: parse_duration_env("PASSWORD_RESET_TTL", "1h")?,
+        })
+    }
+}
+
+// =============================================================================
+// Single Sign-On
+// =============================================================================
+
+#[derive(Debug, Clone)]
+pub struct OidcConfig {
+    /// OpenID Connect issuer URL; SSO is disabled when unset
+    pub issuer: Option<String>,
+    pub client_id: String,
+    /// Unset for public clients, which rely on PKCE alone
+    pub client_secret: Option<String>,
+    pub redirect_url: String,
+    pub scopes: Vec<String>,
+    /// Label for the sign-in button
+    pub provider_name: String,
+    /// Accounts can only be created through SSO
+    pub sso_only: bool,
+}
+
+impl OidcConfig {
+    fn from_env(public_url: &str) -> Result<Self, String> {
+        let issuer = non_empty_env("OIDC_ISSUER");
+        let client_id = non_empty_env("OIDC_CLIENT_ID").unwrap_or_default();
+        let sso_only = parse_bool_env("SSO_ONLY", false)?;
+
+        if issuer.is_some() && client_id.is_empty() {
+            return Err("OIDC_CLIENT_ID is required when OIDC_ISSUER is set".to_string());
+        }
+        if sso_only && issuer.is_none() {
+            return Err("SSO_ONLY requires OIDC_ISSUER to be set".to_string());
+        }
+
+        let mut scopes = parse_list("OIDC_SCOPES");
+        if scopes.is_empty() {
+            scopes = vec!["openid".to_string(), "profile".to_string(), "email".to_string()];
+        }
+
+        Ok(Self {
+            issuer,
+            client_id,
+            client_secret: non_empty_env("OIDC_CLIENT_SECRET"),
+            redirect_url: non_empty_env("OIDC_REDIRECT_URL")
+                .unwrap_or_else(|| format!("{}/login/oidc/callback", public_url)),
+            scopes,
+            provider_name: non_empty_env("OIDC_PROVIDER_NAME").unwrap_or_else(|| "Single Sign-On".to_string()),
+            sso_only,
+        })
+    }
+}
+
+// =============================================================================
+// Helpers
+// =============================================================================
+
+fn parse_env<T: FromStr>(key: &str, default: T) -> Result<T, String> {
+    match env::var(key) {
+        Ok(v) if !v.trim().is_empty() => v
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} has an invalid value: `{}`", key, v)),
+        _ => Ok(default),
+    }
+}
+
+fn non_empty_env(key: &str) -> Option<String> {
+    env::var(key).ok().map(|v| v.trim().to_string()).filter(|v| !v.is_empty())
+}
+
+fn parse_bool_env(key: &str, default: bool) -> Result<bool, String> {
+    match env::var(key).unwrap_or_default().trim().to_lowercase().as_str() {
+        "" => Ok(default),
+        "1" | "true" | "yes" | "on" => Ok(true),
+        "0" | "false" | "no" | "off" => Ok(false),
+        other => Err(format!("{} must be true or false, got `{}`", key, other)),
+    }
+}
+
+/// Comma-separated list, ignoring blank entries
+fn parse_list(key: &str) -> Vec<String> {
+    env::var(key)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/// A duration such as `30s`, `15m`, `24h` or `7d`; bare numbers are seconds
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let (num, unit) = match s.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&s[..i], c),
+        _ => (s, 's'),
+    };
+    let num: u64 = num.parse().map_err(|_| format!("invalid duration `{}`", s))?;
+    let secs = match unit {
+        's' => num,
+        'm' => num.saturating_mul(60),
+        'h' => num.saturating_mul(3600),
+        'd' => num.saturating_mul(86400),
+        _ => return Err("duration unit must be s, m, h or d".to_string()),
+    };
+    if secs == 0 {
+        return Err("duration must be greater than 0".to_string());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+fn parse_duration_env(key: &str, default: &str) -> Result<Duration, String> {
+    let value = env::var(key).unwrap_or_else(|_| default.to_string());
+    parse_duration(&value).map_err(|e| format!("{} has an invalid value: {}", key, e))
+}
@@ -1,6 +1,29 @@
+use chrono::NaiveDateTime;
 use sqlx::{SqlitePool, sqlite::SqlitePoolOptions};
+use std::collections::HashMap;
 use std::env;
 
+use crate::cache::ViewKey;
+use crate::models::{
+    AdminPaste, AdminUser, ApiToken, AuditEntry, HiddenState, InstanceStats, Invite, LoginAttempt, NewPaste, Paste, Report,
+    ReportReason, ReportedPaste, Session, User,
+};
+use crate::utils::content_hash;
+
+pub const USER_SELECT: &str =
+    "SELECT u.id, u.username, u.password_hash, u.created_at, u.totp_enabled, u.email, u.is_admin, u.disabled FROM users u";
+
+/// Paste columns with the body resolved from the shared content store
+pub const PASTE_SELECT: &str =
+    "SELECT p.id, c.content, p.language, p.password_hash, p.expires_at, p.created_at, p.view_count, p.user_id, p.hidden, p.private
+     FROM pastes p JOIN paste_contents c ON c.hash = p.content_hash";
+
+/// Every table `init_db` creates
+const TABLES: &[&str] = &[
+    "pastes", "users", "paste_contents", "login_attempts", "sessions", "login_challenges", "recovery_codes",
+    "api_tokens", "email_tokens", "user_identities", "oidc_logins", "audit_log", "reports", "invites",
+];
+
 pub async fn init_db() -> SqlitePool {
     let database_url = env::var("DATABASE_URL")
         .unwrap_or_else(|_| "sqlite:./oxide-paste.db?mode=rwc".to_string());
@@ -15,7 +38,7 @@ pub async fn init_db() -> SqlitePool {
     sqlx::query(
         "CREATE TABLE IF NOT EXISTS pastes (
             id TEXT PRIMARY KEY,
-            content TEXT NOT NULL,
+            content TEXT NOT NULL DEFAULT '',
             language TEXT,
             password_hash TEXT,
             expires_at DATETIME,
@@ -41,11 +64,236 @@ pub async fn init_db() -> SqlitePool {
     .await
     .expect("Failed to create users table");
 
+    // Create paste contents table (deduplicated bodies)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS paste_contents (
+            hash TEXT PRIMARY KEY,
+            content TEXT NOT NULL,
+            ref_count INTEGER NOT NULL DEFAULT 0
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create paste_contents table");
+
+    // Create login attempts table (sign-in history and lockout)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS login_attempts (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            username TEXT NOT NULL,
+            user_id INTEGER,
+            ip TEXT,
+            user_agent TEXT,
+            success INTEGER NOT NULL,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create login_attempts table");
+
+    // Create sessions table (cookie tokens are stored hashed)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS sessions (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            ip TEXT,
+            user_agent TEXT,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create sessions table");
+
+    // Create login challenges table (password accepted, second factor pending)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS login_challenges (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            attempts INTEGER NOT NULL DEFAULT 0,
+            expires_at DATETIME NOT NULL
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create login_challenges table");
+
+    // Create recovery codes table
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS recovery_codes (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            code_hash TEXT NOT NULL,
+            used_at DATETIME
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create recovery_codes table");
+
+    // Create API tokens table (tokens are stored hashed)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS api_tokens (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            user_id INTEGER NOT NULL,
+            name TEXT NOT NULL,
+            token_hash TEXT NOT NULL UNIQUE,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            last_used_at DATETIME
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create api_tokens table");
+
+    // Create email tokens table (address verification and password resets)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS email_tokens (
+            token_hash TEXT PRIMARY KEY,
+            user_id INTEGER NOT NULL,
+            purpose TEXT NOT NULL,
+            email TEXT,
+            expires_at DATETIME NOT NULL,
+            used_at DATETIME
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create email_tokens table");
+
+    // Create user identities table (accounts linked to an SSO provider)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS user_identities (
+            issuer TEXT NOT NULL,
+            subject TEXT NOT NULL,
+            user_id INTEGER NOT NULL,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (issuer, subject)
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create user_identities table");
+
+    // Create OIDC logins table (state, nonce and PKCE verifier of SSO logins in progress)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS oidc_logins (
+            token_hash TEXT PRIMARY KEY,
+            state TEXT NOT NULL,
+            nonce TEXT NOT NULL,
+            pkce_verifier TEXT NOT NULL,
+            expires_at DATETIME NOT NULL
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create oidc_logins table");
+
+    // Create audit log table (actions taken in the admin area)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS audit_log (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            admin_id INTEGER,
+            admin_username TEXT NOT NULL,
+            action TEXT NOT NULL,
+            target TEXT NOT NULL,
+            details TEXT,
+            ip TEXT,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create audit_log table");
+
+    // Create reports table (abuse reports from visitors, open until resolved)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS reports (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            paste_id TEXT NOT NULL,
+            reason TEXT NOT NULL,
+            details TEXT,
+            reporter_id INTEGER,
+            reporter_ip TEXT,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+            resolved_at DATETIME,
+            resolution TEXT
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create reports table");
+
+    // Create invites table (codes for invite-only registration)
+    sqlx::query(
+        "CREATE TABLE IF NOT EXISTS invites (
+            id INTEGER PRIMARY KEY AUTOINCREMENT,
+            code TEXT NOT NULL UNIQUE,
+            created_by INTEGER NOT NULL,
+            max_uses INTEGER NOT NULL DEFAULT 1,
+            uses INTEGER NOT NULL DEFAULT 0,
+            expires_at DATETIME,
+            created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP
+        )"
+    )
+    .execute(&pool)
+    .await
+    .expect("Failed to create invites table");
+
     // Add user_id column if not exists (for existing databases)
     let _ = sqlx::query("ALTER TABLE pastes ADD COLUMN user_id INTEGER")
         .execute(&pool)
         .await;
 
+    let _ = sqlx::query("ALTER TABLE pastes ADD COLUMN content_hash TEXT")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE pastes ADD COLUMN hidden TEXT")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE pastes ADD COLUMN private INTEGER NOT NULL DEFAULT 0")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN locked_until DATETIME")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN lockout_count INTEGER NOT NULL DEFAULT 0")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN totp_secret TEXT")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN totp_enabled INTEGER NOT NULL DEFAULT 0")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN totp_last_step INTEGER")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN totp_pending_at DATETIME")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN email TEXT")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN is_admin INTEGER NOT NULL DEFAULT 0")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("ALTER TABLE users ADD COLUMN disabled INTEGER NOT NULL DEFAULT 0")
+        .execute(&pool)
+        .await;
+
     // Create indexes
     let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_pastes_expires_at ON pastes(expires_at)")
         .execute(&pool)
@@ -55,17 +303,1334 @@ pub async fn init_db() -> SqlitePool {
         .execute(&pool)
         .await;
 
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_pastes_content_hash ON pastes(content_hash)")
+        .execute(&pool)
+        .await;
+
     let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_users_username ON users(username)")
         .execute(&pool)
         .await;
 
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_login_attempts_user_id ON login_attempts(user_id, created_at)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_login_attempts_created_at ON login_attempts(created_at)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_sessions_user_id ON sessions(user_id)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_recovery_codes_user_id ON recovery_codes(user_id)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_users_email ON users(email)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_email_tokens_user_id ON email_tokens(user_id)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_user_identities_user_id ON user_identities(user_id)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_api_tokens_user_id ON api_tokens(user_id)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_audit_log_created_at ON audit_log(created_at)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_reports_paste_id ON reports(paste_id, resolved_at)")
+        .execute(&pool)
+        .await;
+
+    let _ = sqlx::query("CREATE INDEX IF NOT EXISTS idx_invites_created_by ON invites(created_by)")
+        .execute(&pool)
+        .await;
+
+    migrate_inline_contents(&pool)
+        .await
+        .expect("Failed to migrate paste contents");
+
     pool
 }
 
+/// Tables missing from the database file. Also fails when the paste or user
+/// columns the queries rely on are missing.
+pub async fn missing_tables(pool: &SqlitePool) -> Result<Vec<&'static str>, sqlx::Error> {
+    let present: Vec<(String,)> = sqlx::query_as("SELECT name FROM sqlite_master WHERE type = 'table'")
+        .fetch_all(pool)
+        .await?;
+    let missing: Vec<&'static str> = TABLES
+        .iter()
+        .copied()
+        .filter(|table| !present.iter().any(|(name,)| name == table))
+        .collect();
+
+    if missing.is_empty() {
+        sqlx::query(&format!("{} LIMIT 0", PASTE_SELECT)).execute(pool).await?;
+        sqlx::query(&format!("{} LIMIT 0", USER_SELECT)).execute(pool).await?;
+    }
+    Ok(missing)
+}
+
+/// Move bodies of pastes created before deduplication into `paste_contents`
+async fn migrate_inline_contents(pool: &SqlitePool) -> Result<(), sqlx::Error> {
+    let legacy: Vec<(String, String)> =
+        sqlx::query_as("SELECT id, content FROM pastes WHERE content_hash IS NULL")
+            .fetch_all(pool)
+            .await?;
+
+    if legacy.is_empty() {
+        return Ok(());
+    }
+
+    let mut tx = pool.begin().await?;
+    for (id, content) in &legacy {
+        let hash = content_hash(content);
+        store_content(&mut tx, &hash, content).await?;
+        sqlx::query("UPDATE pastes SET content = '', content_hash = ? WHERE id = ?")
+            .bind(&hash)
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await?;
+
+    tracing::info!(pastes = legacy.len(), "Deduplicated existing pastes");
+    Ok(())
+}
+
+/// Store a body, or take another reference to it if already present
+async fn store_content(
+    tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>,
+    hash: &str,
+    content: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO paste_contents (hash, content, ref_count) VALUES (?, ?, 1)
+         ON CONFLICT(hash) DO UPDATE SET ref_count = ref_count + 1"
+    )
+    .bind(hash)
+    .bind(content)
+    .execute(&mut **tx)
+    .await?;
+    Ok(())
+}
+
+/// Whether an insert failed on a UNIQUE or PRIMARY KEY constraint
+pub fn is_unique_violation(err: &sqlx::Error) -> bool {
+    matches!(err, sqlx::Error::Database(e) if e.is_unique_violation())
+}
+
+pub async fn fetch_paste(pool: &SqlitePool, id: &str) -> Result<Option<Paste>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE p.id = ?", PASTE_SELECT))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn insert_paste(pool: &SqlitePool, paste: &NewPaste<'_>) -> Result<(), sqlx::Error> {
+    let hash = content_hash(paste.content);
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "INSERT INTO pastes (id, content, content_hash, language, password_hash, expires_at, user_id, private)
+         VALUES (?, '', ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(paste.id)
+    .bind(&hash)
+    .bind(paste.language)
+    .bind(paste.password_hash)
+    .bind(paste.expires_at)
+    .bind(paste.user_id)
+    .bind(paste.private)
+    .execute(&mut *tx)
+    .await?;
+
+    store_content(&mut tx, &hash, paste.content).await?;
+
+    tx.commit().await
+}
+
+pub async fn update_paste_password(pool: &SqlitePool, id: &str, password_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE pastes SET password_hash = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Add view counts collected in memory, keyed by paste ID and creation time.
+/// Counts for pastes that are gone are dropped.
+pub async fn add_views(pool: &SqlitePool, views: &HashMap<ViewKey, i64>) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    for ((id, created_at), count) in views {
+        sqlx::query("UPDATE pastes SET view_count = view_count + ? WHERE id = ? AND created_at = ?")
+            .bind(count)
+            .bind(id)
+            .bind(created_at)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Delete a paste and drop its body if this was the last reference
+pub async fn delete_paste(pool: &SqlitePool, id: &str) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let hash: Option<(Option<String>,)> =
+        sqlx::query_as("DELETE FROM pastes WHERE id = ? RETURNING content_hash")
+            .bind(id)
+            .fetch_optional(&mut *tx)
+            .await?;
+
+    let Some((hash,)) = hash else {
+        return Ok(false);
+    };
+
+    // IDs can be reused by vanity slugs, so reports must not outlive the paste
+    sqlx::query("DELETE FROM reports WHERE paste_id = ?")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    if let Some(hash) = hash {
+        sqlx::query("UPDATE paste_contents SET ref_count = ref_count - 1 WHERE hash = ?")
+            .bind(&hash)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM paste_contents WHERE hash = ? AND ref_count <= 0")
+            .bind(&hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+    Ok(true)
+}
+
 pub async fn cleanup_expired_pastes(pool: &SqlitePool) -> Result<u64, sqlx::Error> {
-    let result = sqlx::query("DELETE FROM pastes WHERE expires_at IS NOT NULL AND expires_at < datetime('now')")
+    // Fix the cutoff so the ref-count update and the delete see the same rows
+    let now = chrono::Utc::now().naive_utc();
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        "UPDATE paste_contents SET ref_count = ref_count - (
+            SELECT COUNT(*) FROM pastes
+            WHERE pastes.content_hash = paste_contents.hash
+            AND pastes.expires_at IS NOT NULL AND pastes.expires_at < ?
+         )
+         WHERE hash IN (
+            SELECT content_hash FROM pastes WHERE expires_at IS NOT NULL AND expires_at < ?
+         )"
+    )
+    .bind(now)
+    .bind(now)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM reports WHERE paste_id IN (SELECT id FROM pastes WHERE expires_at IS NOT NULL AND expires_at < ?)")
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    let result = sqlx::query("DELETE FROM pastes WHERE expires_at IS NOT NULL AND expires_at < ?")
+        .bind(now)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM paste_contents WHERE ref_count <= 0")
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(result.rows_affected())
+}
+
+// =============================================================================
+// Login Attempts
+// =============================================================================
+
+pub async fn record_login_attempt(
+    pool: &SqlitePool,
+    username: &str,
+    user_id: Option<i64>,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+    success: bool,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO login_attempts (username, user_id, ip, user_agent, success, created_at) VALUES (?, ?, ?, ?, ?, ?)"
+    )
+    .bind(username)
+    .bind(user_id)
+    .bind(ip)
+    .bind(user_agent)
+    .bind(success)
+    .bind(chrono::Utc::now().naive_utc())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Failed logins for a user since `since`, not counting any before their last success
+pub async fn count_recent_failures(
+    pool: &SqlitePool,
+    user_id: i64,
+    since: NaiveDateTime,
+) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM login_attempts
+         WHERE user_id = ? AND success = 0 AND created_at > ?
+         AND created_at > COALESCE(
+            (SELECT MAX(created_at) FROM login_attempts WHERE user_id = ? AND success = 1), ''
+         )"
+    )
+    .bind(user_id)
+    .bind(since)
+    .bind(user_id)
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+/// `(locked_until, lockout_count)` for a user
+pub async fn lockout_state(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<(Option<NaiveDateTime>, i64), sqlx::Error> {
+    sqlx::query_as("SELECT locked_until, lockout_count FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_one(pool)
+        .await
+}
+
+pub async fn lock_account(
+    pool: &SqlitePool,
+    user_id: i64,
+    until: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET locked_until = ?, lockout_count = lockout_count + 1 WHERE id = ?")
+        .bind(until)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn clear_lockout(pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET locked_until = NULL, lockout_count = 0 WHERE id = ?")
+        .bind(user_id)
         .execute(pool)
         .await?;
-    
+    Ok(())
+}
+
+/// Drop sign-in attempts on unknown usernames older than `unknown_before`
+/// and all attempts older than `before`
+pub async fn prune_login_attempts(
+    pool: &SqlitePool,
+    unknown_before: NaiveDateTime,
+    before: NaiveDateTime,
+) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "DELETE FROM login_attempts
+         WHERE (user_id IS NULL AND created_at < ?) OR created_at < ?"
+    )
+    .bind(unknown_before)
+    .bind(before)
+    .execute(pool)
+    .await?;
     Ok(result.rows_affected())
-}
\ No newline at end of file
+}
+
+pub async fn recent_sign_ins(
+    pool: &SqlitePool,
+    user_id: i64,
+    limit: i64,
+) -> Result<Vec<LoginAttempt>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT ip, user_agent, success, created_at FROM login_attempts
+         WHERE user_id = ? ORDER BY created_at DESC LIMIT ?"
+    )
+    .bind(user_id)
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+// =============================================================================
+// Users and Sessions
+// =============================================================================
+
+pub async fn user_by_id(pool: &SqlitePool, id: i64) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE u.id = ?", USER_SELECT))
+        .bind(id)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn user_by_username(pool: &SqlitePool, username: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE u.username = ?", USER_SELECT))
+        .bind(username)
+        .fetch_optional(pool)
+        .await
+}
+
+pub async fn create_session(
+    pool: &SqlitePool,
+    token_hash: &str,
+    user_id: i64,
+    ip: Option<&str>,
+    user_agent: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO sessions (token_hash, user_id, ip, user_agent, created_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(ip)
+        .bind(user_agent)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn session_user(pool: &SqlitePool, token_hash: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "{} JOIN sessions s ON s.user_id = u.id WHERE s.token_hash = ? AND u.disabled = 0",
+        USER_SELECT
+    ))
+    .bind(token_hash)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_session(pool: &SqlitePool, token_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE token_hash = ?")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_sessions(pool: &SqlitePool, user_id: i64) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT token_hash, ip, user_agent, created_at FROM sessions
+         WHERE user_id = ? ORDER BY created_at DESC"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Sign out one of a user's sessions; false if it is not theirs
+pub async fn revoke_session(pool: &SqlitePool, user_id: i64, token_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND token_hash = ?")
+        .bind(user_id)
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn revoke_all_sessions(pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM sessions WHERE user_id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Sign out every session of a user except the one given
+pub async fn revoke_other_sessions(pool: &SqlitePool, user_id: i64, keep_hash: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query("DELETE FROM sessions WHERE user_id = ? AND token_hash != ?")
+        .bind(user_id)
+        .bind(keep_hash)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected())
+}
+
+pub async fn update_password(pool: &SqlitePool, user_id: i64, password_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET password_hash = ? WHERE id = ?")
+        .bind(password_hash)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Remove a user and everything tied to them. Their pastes are deleted, or
+/// kept without an owner when `keep_pastes` is set.
+pub async fn delete_user(pool: &SqlitePool, user_id: i64, keep_pastes: bool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if keep_pastes {
+        sqlx::query("UPDATE pastes SET user_id = NULL WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    } else {
+        sqlx::query(
+            "UPDATE paste_contents SET ref_count = ref_count - (
+                SELECT COUNT(*) FROM pastes
+                WHERE pastes.content_hash = paste_contents.hash AND pastes.user_id = ?
+             )
+             WHERE hash IN (SELECT content_hash FROM pastes WHERE user_id = ?)"
+        )
+        .bind(user_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+        sqlx::query("DELETE FROM reports WHERE paste_id IN (SELECT id FROM pastes WHERE user_id = ?)")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM pastes WHERE user_id = ?")
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM paste_contents WHERE ref_count <= 0")
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    for table in ["sessions", "login_challenges", "recovery_codes", "api_tokens", "email_tokens", "user_identities", "login_attempts"] {
+        sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", table))
+            .bind(user_id)
+            .execute(&mut *tx)
+            .await?;
+    }
+    sqlx::query("DELETE FROM invites WHERE created_by = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+// =============================================================================
+// Email
+// =============================================================================
+
+/// Single-use links sent by email
+#[derive(Debug, Clone, Copy)]
+pub enum EmailTokenPurpose {
+    Verify,
+    Reset,
+}
+
+impl EmailTokenPurpose {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Verify => "verify",
+            Self::Reset => "reset",
+        }
+    }
+}
+
+pub async fn user_by_email(pool: &SqlitePool, email: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(&format!("{} WHERE u.email = ?", USER_SELECT))
+        .bind(email)
+        .fetch_optional(pool)
+        .await
+}
+
+/// Set a verified address; fails with a unique violation if another account has it
+pub async fn set_email(pool: &SqlitePool, user_id: i64, email: Option<&str>) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET email = ? WHERE id = ?")
+        .bind(email)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Issue a token, replacing any earlier unused one with the same purpose
+pub async fn create_email_token(
+    pool: &SqlitePool,
+    token_hash: &str,
+    user_id: i64,
+    purpose: EmailTokenPurpose,
+    email: Option<&str>,
+    expires_at: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM email_tokens WHERE user_id = ? AND purpose = ?")
+        .bind(user_id)
+        .bind(purpose.as_str())
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query(
+        "INSERT INTO email_tokens (token_hash, user_id, purpose, email, expires_at) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(email)
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+    tx.commit().await
+}
+
+/// Whether a token is unused and unexpired, without using it up
+pub async fn email_token_valid(
+    pool: &SqlitePool,
+    token_hash: &str,
+    purpose: EmailTokenPurpose,
+) -> Result<bool, sqlx::Error> {
+    let row: Option<(i64,)> = sqlx::query_as(
+        "SELECT user_id FROM email_tokens
+         WHERE token_hash = ? AND purpose = ? AND used_at IS NULL AND expires_at > ?"
+    )
+    .bind(token_hash)
+    .bind(purpose.as_str())
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.is_some())
+}
+
+/// Use up a token. Returns `(user_id, email)` if it was valid.
+pub async fn claim_email_token(
+    pool: &SqlitePool,
+    token_hash: &str,
+    purpose: EmailTokenPurpose,
+) -> Result<Option<(i64, Option<String>)>, sqlx::Error> {
+    let now = chrono::Utc::now().naive_utc();
+    sqlx::query_as(
+        "UPDATE email_tokens SET used_at = ?
+         WHERE token_hash = ? AND purpose = ? AND used_at IS NULL AND expires_at > ?
+         RETURNING user_id, email"
+    )
+    .bind(now)
+    .bind(token_hash)
+    .bind(purpose.as_str())
+    .bind(now)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Address waiting on verification, if a link is still outstanding
+pub async fn pending_email(pool: &SqlitePool, user_id: i64) -> Result<Option<String>, sqlx::Error> {
+    let row: Option<(Option<String>,)> = sqlx::query_as(
+        "SELECT email FROM email_tokens
+         WHERE user_id = ? AND purpose = 'verify' AND used_at IS NULL AND expires_at > ?"
+    )
+    .bind(user_id)
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|(email,)| email))
+}
+
+/// Drop a user's outstanding links, and any expired ones while at it
+pub async fn delete_email_tokens(pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM email_tokens WHERE user_id = ? OR expires_at < ?")
+        .bind(user_id)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// =============================================================================
+// Single Sign-On
+// =============================================================================
+
+pub async fn create_oidc_login(
+    pool: &SqlitePool,
+    token_hash: &str,
+    state: &str,
+    nonce: &str,
+    pkce_verifier: &str,
+    expires_at: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO oidc_logins (token_hash, state, nonce, pkce_verifier, expires_at) VALUES (?, ?, ?, ?, ?)"
+    )
+    .bind(token_hash)
+    .bind(state)
+    .bind(nonce)
+    .bind(pkce_verifier)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Remove a login in progress and return its `(state, nonce, pkce_verifier)`
+/// if it hasn't expired. Expired logins are purged along the way.
+pub async fn take_oidc_login(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<(String, String, String)>, sqlx::Error> {
+    let now = chrono::Utc::now().naive_utc();
+    let login = sqlx::query_as(
+        "DELETE FROM oidc_logins WHERE token_hash = ? AND expires_at > ? RETURNING state, nonce, pkce_verifier"
+    )
+    .bind(token_hash)
+    .bind(now)
+    .fetch_optional(pool)
+    .await?;
+
+    sqlx::query("DELETE FROM oidc_logins WHERE expires_at <= ?")
+        .bind(now)
+        .execute(pool)
+        .await?;
+    Ok(login)
+}
+
+pub async fn user_by_identity(pool: &SqlitePool, issuer: &str, subject: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as(&format!(
+        "{} JOIN user_identities i ON i.user_id = u.id WHERE i.issuer = ? AND i.subject = ?",
+        USER_SELECT
+    ))
+    .bind(issuer)
+    .bind(subject)
+    .fetch_optional(pool)
+    .await
+}
+
+/// Create a passwordless account linked to an SSO identity. Fails with a
+/// unique violation if the username is taken.
+pub async fn create_sso_user(
+    pool: &SqlitePool,
+    username: &str,
+    issuer: &str,
+    subject: &str,
+) -> Result<i64, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let user_id = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, '')")
+        .bind(username)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+    sqlx::query("INSERT INTO user_identities (issuer, subject, user_id, created_at) VALUES (?, ?, ?, ?)")
+        .bind(issuer)
+        .bind(subject)
+        .bind(user_id)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(user_id)
+}
+
+// =============================================================================
+// API Tokens
+// =============================================================================
+
+pub async fn create_api_token(
+    pool: &SqlitePool,
+    user_id: i64,
+    name: &str,
+    token_hash: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO api_tokens (user_id, name, token_hash, created_at) VALUES (?, ?, ?, ?)")
+        .bind(user_id)
+        .bind(name)
+        .bind(token_hash)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_api_tokens(pool: &SqlitePool, user_id: i64) -> Result<Vec<ApiToken>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, name, created_at, last_used_at FROM api_tokens
+         WHERE user_id = ? ORDER BY created_at DESC"
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn delete_api_token(pool: &SqlitePool, user_id: i64, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM api_tokens WHERE id = ? AND user_id = ?")
+        .bind(id)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Owner of an API token, recording that the token was just used
+pub async fn api_token_user(pool: &SqlitePool, token_hash: &str) -> Result<Option<User>, sqlx::Error> {
+    let owner: Option<(i64,)> =
+        sqlx::query_as("UPDATE api_tokens SET last_used_at = ? WHERE token_hash = ? RETURNING user_id")
+            .bind(chrono::Utc::now().naive_utc())
+            .bind(token_hash)
+            .fetch_optional(pool)
+            .await?;
+
+    match owner {
+        Some((user_id,)) => Ok(user_by_id(pool, user_id).await?.filter(|u| !u.disabled)),
+        None => Ok(None),
+    }
+}
+
+// =============================================================================
+// Invites
+// =============================================================================
+
+/// Insert a local account, redeeming `invite` in the same transaction.
+/// `Ok(None)` when the invite is unknown, used up or expired.
+pub async fn create_user(
+    pool: &SqlitePool,
+    username: &str,
+    password_hash: &str,
+    invite: Option<&str>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    if let Some(code) = invite {
+        let redeemed = sqlx::query(
+            "UPDATE invites SET uses = uses + 1
+             WHERE code = ? AND uses < max_uses AND (expires_at IS NULL OR expires_at > ?)"
+        )
+        .bind(code)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(&mut *tx)
+        .await?;
+        if redeemed.rows_affected() == 0 {
+            return Ok(None);
+        }
+    }
+
+    let user_id = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
+        .bind(username)
+        .bind(password_hash)
+        .execute(&mut *tx)
+        .await?
+        .last_insert_rowid();
+
+    tx.commit().await?;
+    Ok(Some(user_id))
+}
+
+pub async fn create_invite(
+    pool: &SqlitePool,
+    created_by: i64,
+    code: &str,
+    max_uses: i64,
+    expires_at: Option<NaiveDateTime>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO invites (code, created_by, max_uses, expires_at, created_at) VALUES (?, ?, ?, ?, ?)")
+        .bind(code)
+        .bind(created_by)
+        .bind(max_uses)
+        .bind(expires_at)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn list_invites(pool: &SqlitePool, created_by: i64) -> Result<Vec<Invite>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT id, code, max_uses, uses, expires_at, created_at FROM invites
+         WHERE created_by = ? ORDER BY created_at DESC"
+    )
+    .bind(created_by)
+    .fetch_all(pool)
+    .await
+}
+
+/// Invites that can still be redeemed
+pub async fn active_invite_count(pool: &SqlitePool, created_by: i64) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM invites
+         WHERE created_by = ? AND uses < max_uses AND (expires_at IS NULL OR expires_at > ?)"
+    )
+    .bind(created_by)
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_one(pool)
+    .await?;
+    Ok(count)
+}
+
+pub async fn delete_invite(pool: &SqlitePool, created_by: i64, id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM invites WHERE id = ? AND created_by = ?")
+        .bind(id)
+        .bind(created_by)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+// =============================================================================
+// Reports
+// =============================================================================
+
+/// File a report. A reporter (by account or IP) counts once per paste while
+/// their report is open; returns false for such a repeat.
+pub async fn create_report(
+    pool: &SqlitePool,
+    paste_id: &str,
+    reason: ReportReason,
+    details: Option<&str>,
+    reporter_id: Option<i64>,
+    reporter_ip: Option<&str>,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "INSERT INTO reports (paste_id, reason, details, reporter_id, reporter_ip, created_at)
+         SELECT ?, ?, ?, ?, ?, ?
+         WHERE NOT EXISTS (
+            SELECT 1 FROM reports WHERE paste_id = ? AND resolved_at IS NULL
+            AND ((reporter_id IS NOT NULL AND reporter_id = ?) OR (reporter_ip IS NOT NULL AND reporter_ip = ?))
+         )"
+    )
+    .bind(paste_id)
+    .bind(reason.as_str())
+    .bind(details)
+    .bind(reporter_id)
+    .bind(reporter_ip)
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(paste_id)
+    .bind(reporter_id)
+    .bind(reporter_ip)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn open_report_count(pool: &SqlitePool, paste_id: &str) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM reports WHERE paste_id = ? AND resolved_at IS NULL")
+        .bind(paste_id)
+        .fetch_one(pool)
+        .await?;
+    Ok(count)
+}
+
+/// Close every open report on a paste with the given outcome
+pub async fn resolve_reports(pool: &SqlitePool, paste_id: &str, resolution: &str) -> Result<u64, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE reports SET resolved_at = ?, resolution = ? WHERE paste_id = ? AND resolved_at IS NULL"
+    )
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(resolution)
+    .bind(paste_id)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected())
+}
+
+/// Hold a paste back for review unless it is already hidden
+pub async fn hide_for_review(pool: &SqlitePool, paste_id: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE pastes SET hidden = ? WHERE id = ? AND hidden IS NULL")
+        .bind(HiddenState::Review)
+        .bind(paste_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_hidden(pool: &SqlitePool, paste_id: &str, hidden: Option<HiddenState>) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("UPDATE pastes SET hidden = ? WHERE id = ?")
+        .bind(hidden)
+        .bind(paste_id)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Pastes with open reports, most reported first, each with its reports
+pub async fn report_queue(pool: &SqlitePool) -> Result<Vec<ReportedPaste>, sqlx::Error> {
+    let mut queue: Vec<ReportedPaste> = sqlx::query_as(
+        "SELECT p.id, u.username AS owner, p.hidden, p.password_hash IS NOT NULL AS protected,
+                COUNT(*) AS report_count, MAX(r.created_at) AS last_reported_at
+         FROM reports r
+         JOIN pastes p ON p.id = r.paste_id
+         LEFT JOIN users u ON u.id = p.user_id
+         WHERE r.resolved_at IS NULL
+         GROUP BY p.id
+         ORDER BY report_count DESC, last_reported_at DESC
+         LIMIT ?"
+    )
+    .bind(ADMIN_PAGE_SIZE)
+    .fetch_all(pool)
+    .await?;
+
+    let reports: Vec<Report> = sqlx::query_as(
+        "SELECT r.paste_id, r.reason, r.details, u.username AS reporter, r.reporter_ip, r.created_at
+         FROM reports r LEFT JOIN users u ON u.id = r.reporter_id
+         WHERE r.resolved_at IS NULL
+         ORDER BY r.created_at DESC"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    for report in reports {
+        if let Some(item) = queue.iter_mut().find(|p| p.id == report.paste_id) {
+            item.reports.push(report);
+        }
+    }
+    Ok(queue)
+}
+
+// =============================================================================
+// Admin
+// =============================================================================
+
+/// Most rows an admin search returns
+const ADMIN_PAGE_SIZE: i64 = 100;
+
+/// Make the listed accounts administrators; returns how many were promoted
+pub async fn grant_admins(pool: &SqlitePool, usernames: &[String]) -> Result<u64, sqlx::Error> {
+    let mut promoted = 0;
+    for username in usernames {
+        let result = sqlx::query("UPDATE users SET is_admin = 1 WHERE username = ? AND is_admin = 0")
+            .bind(username)
+            .execute(pool)
+            .await?;
+        promoted += result.rows_affected();
+    }
+    Ok(promoted)
+}
+
+pub async fn set_admin(pool: &SqlitePool, user_id: i64, is_admin: bool) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET is_admin = ? WHERE id = ?")
+        .bind(is_admin)
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Disable or re-enable an account. Disabling also signs it out everywhere.
+pub async fn set_disabled(pool: &SqlitePool, user_id: i64, disabled: bool) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE users SET disabled = ? WHERE id = ?")
+        .bind(disabled)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    if disabled {
+        for table in ["sessions", "login_challenges"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE user_id = ?", table))
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+    }
+    tx.commit().await
+}
+
+/// Pastes whose ID, owner or body contains `query`, newest first
+pub async fn admin_search_pastes(pool: &SqlitePool, query: &str) -> Result<Vec<AdminPaste>, sqlx::Error> {
+    let pattern = format!("%{}%", escape_like(query));
+    sqlx::query_as(
+        "SELECT p.id, p.language, u.username AS owner, LENGTH(c.content) AS size,
+                p.password_hash IS NOT NULL AS protected, p.private, p.hidden, p.expires_at, p.created_at, p.view_count
+         FROM pastes p
+         JOIN paste_contents c ON c.hash = p.content_hash
+         LEFT JOIN users u ON u.id = p.user_id
+         WHERE ? = '' OR p.id LIKE ? ESCAPE '\\' OR u.username LIKE ? ESCAPE '\\' OR c.content LIKE ? ESCAPE '\\'
+         ORDER BY p.created_at DESC LIMIT ?"
+    )
+    .bind(query)
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(ADMIN_PAGE_SIZE)
+    .fetch_all(pool)
+    .await
+}
+
+/// Accounts whose username or email contains `query`, newest first
+pub async fn admin_search_users(pool: &SqlitePool, query: &str) -> Result<Vec<AdminUser>, sqlx::Error> {
+    let pattern = format!("%{}%", escape_like(query));
+    sqlx::query_as(
+        "SELECT u.id, u.username, u.email, u.created_at, u.totp_enabled, u.is_admin, u.disabled,
+                (SELECT COUNT(*) FROM pastes p WHERE p.user_id = u.id) AS paste_count
+         FROM users u
+         WHERE ? = '' OR u.username LIKE ? ESCAPE '\\' OR u.email LIKE ? ESCAPE '\\'
+         ORDER BY u.created_at DESC LIMIT ?"
+    )
+    .bind(query)
+    .bind(&pattern)
+    .bind(&pattern)
+    .bind(ADMIN_PAGE_SIZE)
+    .fetch_all(pool)
+    .await
+}
+
+/// Escape `%`, `_` and the escape character itself for a LIKE pattern
+fn escape_like(input: &str) -> String {
+    input.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_")
+}
+
+pub async fn instance_stats(pool: &SqlitePool) -> Result<InstanceStats, sqlx::Error> {
+    let (pastes, protected_pastes, expiring_pastes, logical_bytes): (i64, i64, i64, i64) = sqlx::query_as(
+        "SELECT COUNT(*),
+                COALESCE(SUM(p.password_hash IS NOT NULL), 0),
+                COALESCE(SUM(p.expires_at IS NOT NULL), 0),
+                COALESCE(SUM(LENGTH(c.content)), 0)
+         FROM pastes p JOIN paste_contents c ON c.hash = p.content_hash"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let (users, disabled_users): (i64, i64) =
+        sqlx::query_as("SELECT COUNT(*), COALESCE(SUM(disabled), 0) FROM users")
+            .fetch_one(pool)
+            .await?;
+
+    let (reported_pastes,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(DISTINCT r.paste_id) FROM reports r JOIN pastes p ON p.id = r.paste_id
+         WHERE r.resolved_at IS NULL"
+    )
+    .fetch_one(pool)
+    .await?;
+
+    let (content_bytes,): (i64,) = sqlx::query_as("SELECT COALESCE(SUM(LENGTH(content)), 0) FROM paste_contents")
+        .fetch_one(pool)
+        .await?;
+
+    let (database_bytes,): (i64,) =
+        sqlx::query_as("SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size()")
+            .fetch_one(pool)
+            .await?;
+
+    let top_languages = sqlx::query_as(
+        "SELECT COALESCE(language, 'plaintext') AS lang, COUNT(*) AS n FROM pastes
+         GROUP BY lang ORDER BY n DESC, lang LIMIT 10"
+    )
+    .fetch_all(pool)
+    .await?;
+
+    Ok(InstanceStats {
+        pastes,
+        protected_pastes,
+        expiring_pastes,
+        users,
+        disabled_users,
+        reported_pastes,
+        content_bytes,
+        logical_bytes,
+        database_bytes,
+        top_languages,
+    })
+}
+
+/// Bytes of (deduplicated) paste content, and the size of the database file
+pub async fn storage_bytes(pool: &SqlitePool) -> Result<(i64, i64), sqlx::Error> {
+    sqlx::query_as(
+        "SELECT (SELECT COALESCE(SUM(LENGTH(content)), 0) FROM paste_contents),
+                (SELECT page_count * page_size FROM pragma_page_count(), pragma_page_size())"
+    )
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn record_audit(
+    pool: &SqlitePool,
+    admin: &User,
+    action: &str,
+    target: &str,
+    details: Option<&str>,
+    ip: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO audit_log (admin_id, admin_username, action, target, details, ip, created_at)
+         VALUES (?, ?, ?, ?, ?, ?, ?)"
+    )
+    .bind(admin.id)
+    .bind(&admin.username)
+    .bind(action)
+    .bind(target)
+    .bind(details)
+    .bind(ip)
+    .bind(chrono::Utc::now().naive_utc())
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn recent_audit_entries(pool: &SqlitePool, limit: i64) -> Result<Vec<AuditEntry>, sqlx::Error> {
+    sqlx::query_as(
+        "SELECT admin_username, action, target, details, ip, created_at FROM audit_log
+         ORDER BY id DESC LIMIT ?"
+    )
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+// =============================================================================
+// Two-Factor Authentication
+// =============================================================================
+
+pub async fn totp_secret(pool: &SqlitePool, user_id: i64) -> Result<Option<String>, sqlx::Error> {
+    let row: Option<(Option<String>,)> = sqlx::query_as("SELECT totp_secret FROM users WHERE id = ?")
+        .bind(user_id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.and_then(|(secret,)| secret))
+}
+
+/// The secret awaiting confirmation and when it was created
+pub async fn pending_totp_secret(
+    pool: &SqlitePool,
+    user_id: i64,
+) -> Result<Option<(String, NaiveDateTime)>, sqlx::Error> {
+    let row: Option<(Option<String>, Option<NaiveDateTime>)> = sqlx::query_as(
+        "SELECT totp_secret, totp_pending_at FROM users WHERE id = ? AND totp_enabled = 0"
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+    Ok(row.and_then(|(secret, created)| secret.zip(created)))
+}
+
+/// Store a secret awaiting confirmation; it only takes effect once enabled
+pub async fn set_pending_totp_secret(pool: &SqlitePool, user_id: i64, secret: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "UPDATE users SET totp_secret = ?, totp_pending_at = ?, totp_last_step = NULL
+         WHERE id = ? AND totp_enabled = 0"
+    )
+    .bind(secret)
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn enable_totp(pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE users SET totp_enabled = 1 WHERE id = ?")
+        .bind(user_id)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+pub async fn disable_totp(pool: &SqlitePool, user_id: i64) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("UPDATE users SET totp_enabled = 0, totp_secret = NULL, totp_pending_at = NULL, totp_last_step = NULL WHERE id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await
+}
+
+/// Record the time step of an accepted code. Fails (returns false) if that
+/// step or a later one was already used, which blocks code replay.
+pub async fn claim_totp_step(pool: &SqlitePool, user_id: i64, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE users SET totp_last_step = ? WHERE id = ? AND (totp_last_step IS NULL OR totp_last_step < ?)"
+    )
+    .bind(step)
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() == 1)
+}
+
+pub async fn replace_recovery_codes(
+    pool: &SqlitePool,
+    user_id: i64,
+    code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = ?")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    for hash in code_hashes {
+        sqlx::query("INSERT INTO recovery_codes (user_id, code_hash) VALUES (?, ?)")
+            .bind(user_id)
+            .bind(hash)
+            .execute(&mut *tx)
+            .await?;
+    }
+    tx.commit().await
+}
+
+/// Mark a recovery code as used; false if it does not exist or was used before
+pub async fn use_recovery_code(pool: &SqlitePool, user_id: i64, code_hash: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        "UPDATE recovery_codes SET used_at = ? WHERE user_id = ? AND code_hash = ? AND used_at IS NULL"
+    )
+    .bind(chrono::Utc::now().naive_utc())
+    .bind(user_id)
+    .bind(code_hash)
+    .execute(pool)
+    .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn remaining_recovery_codes(pool: &SqlitePool, user_id: i64) -> Result<i64, sqlx::Error> {
+    let (count,): (i64,) =
+        sqlx::query_as("SELECT COUNT(*) FROM recovery_codes WHERE user_id = ? AND used_at IS NULL")
+            .bind(user_id)
+            .fetch_one(pool)
+            .await?;
+    Ok(count)
+}
+
+pub async fn create_login_challenge(
+    pool: &SqlitePool,
+    token_hash: &str,
+    user_id: i64,
+    expires_at: NaiveDateTime,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("INSERT INTO login_challenges (token_hash, user_id, expires_at) VALUES (?, ?, ?)")
+        .bind(token_hash)
+        .bind(user_id)
+        .bind(expires_at)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// User waiting on a second factor, counting this as one more attempt.
+/// Returns `(user_id, attempts)` for unexpired challenges.
+pub async fn touch_login_challenge(
+    pool: &SqlitePool,
+    token_hash: &str,
+) -> Result<Option<(i64, i64)>, sqlx::Error> {
+    sqlx::query_as(
+        "UPDATE login_challenges SET attempts = attempts + 1
+         WHERE token_hash = ? AND expires_at > ?
+         RETURNING user_id, attempts"
+    )
+    .bind(token_hash)
+    .bind(chrono::Utc::now().naive_utc())
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_login_challenge(pool: &SqlitePool, token_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_challenges WHERE token_hash = ? OR expires_at < ?")
+        .bind(token_hash)
+        .bind(chrono::Utc::now().naive_utc())
+        .execute(pool)
+        .await?;
+    Ok(())
+}
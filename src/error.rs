@@ -0,0 +1,203 @@
+use askama::Template;
+use axum::{
+    body::Body,
+    extract::Request,
+    http::{header, HeaderMap, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use serde::Serialize;
+use tracing::error;
+
+use crate::handlers::{ErrorTemplate, NotFoundTemplate};
+use crate::ratelimit::bearer_token;
+
+// =============================================================================
+// Errors
+// =============================================================================
+
+/// Everything a handler can fail with. Turned into a status code and a short
+/// message here; `error_pages` then dresses it up for the client.
+#[derive(Debug)]
+pub enum AppError {
+    NotFound,
+    Unauthorized(&'static str),
+    Forbidden(&'static str),
+    TooLarge,
+    Database(sqlx::Error),
+    Template(askama::Error),
+    /// Something failed that was already logged where it happened
+    Internal(&'static str),
+}
+
+impl From<sqlx::Error> for AppError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Database(e)
+    }
+}
+
+impl From<askama::Error> for AppError {
+    fn from(e: askama::Error) -> Self {
+        Self::Template(e)
+    }
+}
+
+impl AppError {
+    fn status(&self) -> StatusCode {
+        match self {
+            Self::NotFound | Self::Database(sqlx::Error::RowNotFound) => StatusCode::NOT_FOUND,
+            Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            Self::Forbidden(_) => StatusCode::FORBIDDEN,
+            Self::TooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Database(e) if database_unavailable(e) => StatusCode::SERVICE_UNAVAILABLE,
+            Self::Database(_) | Self::Template(_) | Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message(&self) -> &'static str {
+        match self {
+            Self::Unauthorized(msg) | Self::Forbidden(msg) | Self::Internal(msg) => msg,
+            Self::TooLarge => "The data you submitted is larger than this server accepts.",
+            _ => match self.status() {
+                StatusCode::NOT_FOUND => "The page you're looking for doesn't exist or has expired.",
+                StatusCode::SERVICE_UNAVAILABLE => "The server is busy right now. Please try again in a moment.",
+                _ => "Something went wrong on our side. Please try again later.",
+            },
+        }
+    }
+}
+
+/// Errors that should clear up by themselves: a locked database file or a
+/// pool with no free connection
+fn database_unavailable(e: &sqlx::Error) -> bool {
+    match e {
+        sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed | sqlx::Error::Io(_) => true,
+        // SQLITE_BUSY and SQLITE_LOCKED, including their extended codes
+        sqlx::Error::Database(e) => e
+            .code()
+            .and_then(|code| code.parse::<i32>().ok())
+            .is_some_and(|code| matches!(code & 0xff, 5 | 6)),
+        _ => false,
+    }
+}
+
+/// What `error_pages` needs to render the error, riding along on the
+/// response
+#[derive(Clone)]
+struct ErrorPage {
+    status: StatusCode,
+    message: &'static str,
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        match &self {
+            Self::Database(sqlx::Error::RowNotFound) => {}
+            Self::Database(e) => error!(error = %e, "Database error"),
+            Self::Template(e) => error!(error = %e, "Failed to render template"),
+            _ => {}
+        }
+
+        let page = ErrorPage {
+            status: self.status(),
+            message: self.message(),
+        };
+        let mut response = (page.status, page.message).into_response();
+        response.extensions_mut().insert(page);
+        response
+    }
+}
+
+/// Render a template as a page, or as an error when that fails
+pub fn render(template: &impl Template) -> Result<Html<String>, AppError> {
+    Ok(Html(template.render()?))
+}
+
+// =============================================================================
+// Error Pages
+// =============================================================================
+
+#[derive(Clone, Copy)]
+enum Format {
+    Html,
+    Json,
+    Text,
+}
+
+impl Format {
+    fn of(headers: &HeaderMap) -> Self {
+        let accept = headers.get(header::ACCEPT).and_then(|v| v.to_str().ok()).unwrap_or_default();
+        if accept.contains("application/json") || bearer_token(headers).is_some() {
+            Self::Json
+        } else if accept.contains("text/html") {
+            Self::Html
+        } else {
+            Self::Text
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+}
+
+/// Give errors a body the client can use: a rendered page for browsers, JSON
+/// for API clients and plain text for everything else (`curl`). Bodies that
+/// are too large get the same treatment even though axum rejects them before
+/// any handler runs.
+pub async fn error_pages(request: Request, next: Next) -> Response {
+    let format = Format::of(request.headers());
+    let response = next.run(request).await;
+
+    let page = match response.extensions().get::<ErrorPage>() {
+        Some(page) => page.clone(),
+        None if response.status() == StatusCode::PAYLOAD_TOO_LARGE && !is_html(&response) => ErrorPage {
+            status: StatusCode::PAYLOAD_TOO_LARGE,
+            message: AppError::TooLarge.message(),
+        },
+        None => return response,
+    };
+
+    let (mut parts, _) = response.into_parts();
+    // A handler may have put its own status on top of a failed render
+    parts.status = page.status;
+    parts.headers.remove(header::CONTENT_LENGTH);
+
+    let (content_type, body) = match format {
+        Format::Json => {
+            let body = serde_json::to_string(&ErrorBody { error: page.message }).unwrap_or_default();
+            ("application/json", body)
+        }
+        Format::Html => match error_html(&page) {
+            Ok(html) => ("text/html; charset=utf-8", html),
+            Err(e) => {
+                error!(error = %e, "Failed to render error page");
+                ("text/plain; charset=utf-8", page.message.to_string())
+            }
+        },
+        Format::Text => ("text/plain; charset=utf-8", page.message.to_string()),
+    };
+    parts.headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+    Response::from_parts(parts, Body::from(body))
+}
+
+fn error_html(page: &ErrorPage) -> askama::Result<String> {
+    if page.status == StatusCode::NOT_FOUND {
+        return NotFoundTemplate.render();
+    }
+    ErrorTemplate {
+        code: page.status.as_u16(),
+        title: page.status.canonical_reason().unwrap_or("Error").to_string(),
+        message: page.message.to_string(),
+    }
+    .render()
+}
+
+fn is_html(response: &Response) -> bool {
+    response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/html"))
+}
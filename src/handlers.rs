@@ -1,17 +1,62 @@
 use axum::{
-    extract::{Path, State},
-    http::StatusCode,
-    response::{Html, IntoResponse, Redirect},
-    Form,
+    extract::{rejection::FormRejection, Path, Query, Request, State},
+    middleware::Next,
+    http::{header, HeaderMap, HeaderName, StatusCode},
+    response::{Html, IntoResponse, Redirect, Response},
+    Form, Json,
 };
-use axum_extra::extract::cookie::{Cookie, CookieJar};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
 use askama::Template;
+use serde::Serialize;
 use sqlx::SqlitePool;
+use tracing::{error, info, warn, Instrument};
 
-use crate::models::{CreatePasteForm, Expiration, LoginForm, PasswordForm, Paste, RegisterForm, User};
-use crate::utils::{generate_id, hash_password, verify_password};
+use crate::assets;
+use crate::cache::CachedPaste;
+use crate::config::{LockoutConfig, PasswordConfig, RegistrationMode, SecretPolicy};
+use crate::db::{self, EmailTokenPurpose};
+use crate::error::{render, AppError};
+use crate::logging::LogErr;
+use crate::mail;
+use crate::metrics::{self, LoginFailure};
+use crate::oidc;
+use crate::models::{
+    AdminActionForm, AdminPaste, AdminSearchQuery, AdminUser, AuditEntry, HiddenState, InstanceStats, ReportForm,
+    ReportReason, ReportedPaste, ApiToken, CreateInviteForm, Invite, RegisterQuery, ChangePasswordForm, OidcCallbackQuery, CreatePasteForm, CreateTokenForm, DeleteAccountForm, DisableTwoFactorForm,
+    EmailForm, Expiration, ForgotPasswordForm, LoginAttempt, LoginForm, NewPaste, PasswordForm, Paste, RegisterForm,
+    ResetPasswordForm, RevokeSessionForm, Session, TokenQuery, TwoFactorForm, User,
+};
+use crate::ratelimit::{bearer_token, Bucket, Client};
+use crate::secrets::Finding;
+use crate::security::RAW_CSP;
+use crate::spam::Challenge;
+use crate::state::AppState;
+use crate::twofactor;
+use crate::utils::{
+    dummy_hash, generate_api_token, generate_id, generate_token, hash_password, hash_token, matches_glob,
+    needs_rehash, validate_slug, verify_password, API_TOKEN_PREFIX,
+};
 
 const SESSION_COOKIE: &str = "oxide_session";
+const CHALLENGE_COOKIE: &str = "oxide_2fa";
+const LOGIN_CHALLENGE_MINUTES: i64 = 5;
+const MAX_CHALLENGE_ATTEMPTS: i64 = 5;
+/// How long an unconfirmed authenticator secret is shown again before a new one
+const PENDING_TOTP_HOURS: i64 = 24;
+const MAX_ID_ATTEMPTS: usize = 5;
+const EMAIL_VERIFY_HOURS: i64 = 24;
+const OIDC_COOKIE: &str = "oxide_oidc";
+const OIDC_LOGIN_MINUTES: i64 = 10;
+const MAX_USERNAME_ATTEMPTS: usize = 20;
+const AUDIT_LOG_ENTRIES: i64 = 50;
+const MAX_REPORT_DETAILS: usize = 1000;
+const MAX_INVITE_USES: i64 = 1000;
+const INVITE_CODE_LENGTH: usize = 12;
+/// Letters and digits that are hard to mix up when an invite is typed in
+const INVITE_ALPHABET: [char; 31] = [
+    'a', 'b', 'c', 'd', 'e', 'f', 'g', 'h', 'j', 'k', 'm', 'n', 'p', 'q', 'r', 's', 't', 'u', 'v', 'w', 'x', 'y',
+    'z', '2', '3', '4', '5', '6', '7', '8', '9',
+];
 
 // =============================================================================
 // Templates
@@ -22,18 +67,49 @@ const SESSION_COOKIE: &str = "oxide_session";
 pub struct IndexTemplate {
     pub languages: Vec<(&'static str, &'static str)>,
     pub user: Option<User>,
+    pub error: Option<String>,
+    pub draft: CreatePasteForm,
+    /// Likely secrets found in the draft
+    pub findings: Vec<Finding>,
+    /// The paste can't be stored publicly as it is
+    pub secrets_blocked: bool,
+    /// Proof of work the browser solves before submitting, for anonymous visitors
+    pub challenge: Option<Challenge>,
+}
+
+impl IndexTemplate {
+    fn is_language(&self, value: &str) -> bool {
+        self.draft.language.as_deref() == Some(value)
+    }
+
+    fn is_expiration(&self, value: &str) -> bool {
+        self.draft.expiration.as_deref() == Some(value)
+    }
 }
 
 #[derive(Template)]
 #[template(path = "view.html")]
-pub struct ViewTemplate {
-    pub paste: Paste,
+pub struct ViewTemplate<'a> {
+    pub paste: &'a Paste,
+    /// The body, escaped once when the paste was cached
+    pub code_html: &'a str,
+    pub lines: usize,
+    pub views: i64,
     pub formatted_date: String,
     pub expires_in: Option<String>,
     pub user: Option<User>,
     pub is_owner: bool,
 }
 
+#[derive(Template)]
+#[template(path = "report.html")]
+pub struct ReportTemplate {
+    pub id: String,
+    pub reasons: [ReportReason; 5],
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
 #[derive(Template)]
 #[template(path = "password.html")]
 pub struct PasswordTemplate {
@@ -45,16 +121,80 @@ pub struct PasswordTemplate {
 #[template(path = "not_found.html")]
 pub struct NotFoundTemplate;
 
+#[derive(Template)]
+#[template(path = "error.html")]
+pub struct ErrorTemplate {
+    pub code: u16,
+    pub title: String,
+    pub message: String,
+}
+
 #[derive(Template)]
 #[template(path = "login.html")]
 pub struct LoginTemplate {
     pub error: Option<String>,
+    /// Label of the SSO button, when single sign-on is configured
+    pub sso_name: Option<String>,
+    pub allow_register: bool,
+}
+
+impl LoginTemplate {
+    fn new(state: &AppState, error: Option<String>) -> Self {
+        Self {
+            error,
+            sso_name: state.sso.as_ref().map(|p| p.name().to_string()),
+            allow_register: !state.config.oidc.sso_only
+                && state.config.auth.registration != RegistrationMode::Closed,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "login_2fa.html")]
+pub struct LoginTwoFactorTemplate {
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "two_factor.html")]
+pub struct TwoFactorTemplate {
+    pub user: User,
+    pub required: bool,
+    pub qr_svg: Option<String>,
+    pub secret: Option<String>,
+    pub recovery_codes: Vec<String>,
+    pub remaining_codes: i64,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "forgot_password.html")]
+pub struct ForgotPasswordTemplate {
+    pub enabled: bool,
+    pub sent: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "reset_password.html")]
+pub struct ResetPasswordTemplate {
+    /// `None` when the link is invalid or expired
+    pub token: Option<String>,
+    pub done: bool,
+    pub error: Option<String>,
 }
 
 #[derive(Template)]
 #[template(path = "register.html")]
 pub struct RegisterTemplate {
     pub error: Option<String>,
+    /// Registration is turned off; only the notice is shown
+    pub closed: bool,
+    pub invite_required: bool,
+    /// Invite code from the link or the previous attempt
+    pub invite: String,
+    /// Globs new usernames must match, shown as a hint
+    pub username_patterns: Vec<String>,
 }
 
 #[derive(Template)]
@@ -62,6 +202,77 @@ pub struct RegisterTemplate {
 pub struct DashboardTemplate {
     pub user: User,
     pub pastes: Vec<Paste>,
+    pub sign_ins: Vec<LoginAttempt>,
+}
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+pub struct SettingsTemplate {
+    pub user: User,
+    pub sessions: Vec<Session>,
+    pub current_session: String,
+    pub tokens: Vec<ApiToken>,
+    /// Freshly created API token, shown once
+    pub new_token: Option<String>,
+    pub email_enabled: bool,
+    /// Address waiting on its verification link
+    pub pending_email: Option<String>,
+    /// Registration is invite-only and this user may hand out invites
+    pub can_invite: bool,
+    pub invites: Vec<Invite>,
+    /// Base for invite links
+    pub public_url: String,
+    pub notice: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin.html")]
+pub struct AdminTemplate {
+    pub user: User,
+    pub stats: InstanceStats,
+    pub audit_log: Vec<AuditEntry>,
+}
+
+impl AdminTemplate {
+    fn bytes(&self, bytes: &i64) -> String {
+        format_bytes((*bytes).max(0) as usize)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_pastes.html")]
+pub struct AdminPastesTemplate {
+    pub user: User,
+    pub query: String,
+    pub pastes: Vec<AdminPaste>,
+    pub notice: Option<String>,
+    pub error: Option<String>,
+}
+
+impl AdminPastesTemplate {
+    fn bytes(&self, bytes: &i64) -> String {
+        format_bytes((*bytes).max(0) as usize)
+    }
+}
+
+#[derive(Template)]
+#[template(path = "admin_reports.html")]
+pub struct AdminReportsTemplate {
+    pub user: User,
+    pub queue: Vec<ReportedPaste>,
+    pub notice: Option<String>,
+    pub error: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_users.html")]
+pub struct AdminUsersTemplate {
+    pub user: User,
+    pub query: String,
+    pub users: Vec<AdminUser>,
+    pub notice: Option<String>,
+    pub error: Option<String>,
 }
 
 #[derive(Template)]
@@ -108,135 +319,506 @@ fn get_supported_languages() -> Vec<(&'static str, &'static str)> {
 
 async fn get_current_user(pool: &SqlitePool, jar: &CookieJar) -> Option<User> {
     let session = jar.get(SESSION_COOKIE)?;
-    let user_id: i64 = session.value().parse().ok()?;
-    
-    sqlx::query_as::<_, User>("SELECT id, username, password_hash, created_at FROM users WHERE id = ?")
-        .bind(user_id)
-        .fetch_optional(pool)
+    db::session_user(pool, &hash_token(session.value()))
+        .await
+        .log_err("Failed to look up session")?
+}
+
+/// Signed-in user along with the hash of their session token
+async fn current_session(pool: &SqlitePool, jar: &CookieJar) -> Option<(User, String)> {
+    let token_hash = hash_token(jar.get(SESSION_COOKIE)?.value());
+    let user = db::session_user(pool, &token_hash).await.log_err("Failed to look up session")??;
+    Some((user, token_hash))
+}
+
+/// The user behind a request, from an API token sent as `Authorization:
+/// Bearer` or else the session cookie. A token that doesn't match any
+/// account is refused rather than treated as anonymous.
+async fn authenticated_user(pool: &SqlitePool, jar: &CookieJar, headers: &HeaderMap) -> Result<Option<User>, Response> {
+    match bearer_token(headers).filter(|t| t.starts_with(API_TOKEN_PREFIX)) {
+        Some(token) => match db::api_token_user(pool, &hash_token(token)).await.log_err("Failed to look up API token") {
+            Some(Some(user)) => Ok(Some(user)),
+            _ => Err(AppError::Unauthorized("Invalid API token").into_response()),
+        },
+        None => Ok(get_current_user(pool, jar).await),
+    }
+}
+
+fn user_agent(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get(header::USER_AGENT)
+        .and_then(|v| v.to_str().ok())
+        .map(|ua| ua.chars().take(255).collect())
+}
+
+/// Create a server-side session and hand its token to the browser
+async fn start_session(
+    pool: &SqlitePool,
+    jar: CookieJar,
+    user_id: i64,
+    client: &Client,
+    headers: &HeaderMap,
+) -> Result<CookieJar, sqlx::Error> {
+    let token = generate_token();
+    let ip = client.ip.map(|ip| ip.to_string());
+    db::create_session(pool, &hash_token(&token), user_id, ip.as_deref(), user_agent(headers).as_deref()).await?;
+
+    let cookie = Cookie::build((SESSION_COOKIE, token))
+        .path("/")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build();
+    Ok(jar.add(cookie))
+}
+
+/// Park a sign-in that still needs its second factor and point the browser at it
+async fn start_two_factor_challenge(
+    pool: &SqlitePool,
+    jar: CookieJar,
+    user_id: i64,
+) -> Result<CookieJar, sqlx::Error> {
+    let token = generate_token();
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(LOGIN_CHALLENGE_MINUTES);
+    db::create_login_challenge(pool, &hash_token(&token), user_id, expires_at).await?;
+
+    let cookie = Cookie::build((CHALLENGE_COOKIE, token))
+        .path("/login")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build();
+    Ok(jar.add(cookie))
+}
+
+/// Promote a freshly created account if it is listed in ADMIN_USERNAMES
+async fn bootstrap_admin(state: &AppState, user_id: i64, username: &str) {
+    if state.config.auth.admin_usernames.iter().any(|a| a == username) {
+        db::set_admin(&state.pool, user_id, true).await.log_err("Failed to grant admin from ADMIN_USERNAMES");
+    }
+}
+
+/// A fresh hash for a password that was just verified, if the stored one is
+/// bcrypt or uses other Argon2 settings than configured
+fn upgraded_hash(config: &PasswordConfig, stored: &str, password: &str) -> Option<String> {
+    if !needs_rehash(stored, config) {
+        return None;
+    }
+    hash_password(password, config).log_err("Failed to rehash password")
+}
+
+fn disabled_response(state: &AppState) -> Response {
+    let template = LoginTemplate::new(state, Some("This account has been disabled".to_string()));
+    (StatusCode::FORBIDDEN, render(&template)).into_response()
+}
+
+/// Remaining lockout time in seconds, if the account is locked
+async fn lockout_remaining(pool: &SqlitePool, user_id: i64) -> Option<u64> {
+    let (locked_until, _) = db::lockout_state(pool, user_id).await.log_err("Failed to read lockout state")?;
+    let remaining = locked_until?.signed_duration_since(chrono::Utc::now().naive_utc()).num_seconds();
+    (remaining > 0).then_some(remaining as u64)
+}
+
+fn locked_response(state: &AppState, remaining: u64) -> Response {
+    let message = format!(
+        "This account is temporarily locked after too many failed sign-ins. Try again in {}.",
+        format_wait(remaining)
+    );
+    let template = LoginTemplate::new(state, Some(message));
+    (StatusCode::FORBIDDEN, render(&template)).into_response()
+}
+
+/// Lock the account once it has too many recent failures. Each lockout in a
+/// row doubles the duration, up to the configured maximum.
+async fn apply_lockout(pool: &SqlitePool, config: &LockoutConfig, user_id: i64) {
+    if config.threshold == 0 {
+        return;
+    }
+
+    let now = chrono::Utc::now().naive_utc();
+    let window = chrono::Duration::from_std(config.window).unwrap_or_else(|_| chrono::Duration::minutes(15));
+    let failures = db::count_recent_failures(pool, user_id, now - window)
+        .await
+        .log_err("Failed to count failed sign-ins")
+        .unwrap_or(0);
+    if failures < i64::from(config.threshold) {
+        return;
+    }
+
+    let previous = db::lockout_state(pool, user_id)
         .await
-        .ok()?
+        .log_err("Failed to read lockout state")
+        .map_or(0, |(_, count)| count);
+    let factor = 1u32 << previous.clamp(0, 16);
+    let duration = config.base.saturating_mul(factor).min(config.max);
+    let until = now + chrono::Duration::from_std(duration).unwrap_or_else(|_| chrono::Duration::hours(24));
+    db::lock_account(pool, user_id, until).await.log_err("Failed to lock account");
+}
+
+/// Send users who still need to enroll in 2FA to the enrollment page when
+/// the instance requires it
+pub async fn require_two_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    request: Request,
+    next: Next,
+) -> Response {
+    const ALLOWED: &[&str] = &[
+        "/2fa",
+        "/2fa/enable",
+        "/2fa/restart",
+        "/login",
+        "/login/2fa",
+        "/login/oidc",
+        "/login/oidc/callback",
+        "/logout",
+        "/healthz",
+        "/readyz",
+        "/metrics",
+    ];
+
+    // Checked before the session lookup so assets and probes stay cheap
+    let path = request.uri().path();
+    if !state.config.auth.require_two_factor || ALLOWED.contains(&path) || path.starts_with("/static/") {
+        return next.run(request).await;
+    }
+
+    match get_current_user(&state.pool, &jar).await {
+        Some(user) if !user.totp_enabled => Redirect::to("/2fa").into_response(),
+        _ => next.run(request).await,
+    }
 }
 
 // =============================================================================
 // Auth Handlers
 // =============================================================================
 
-pub async fn login_page() -> impl IntoResponse {
-    Html(LoginTemplate { error: None }.render().unwrap())
+pub async fn login_page(State(state): State<AppState>) -> impl IntoResponse {
+    render(&LoginTemplate::new(&state, None))
 }
 
 pub async fn login(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
     jar: CookieJar,
     Form(form): Form<LoginForm>,
 ) -> impl IntoResponse {
-    let user: Option<User> = sqlx::query_as(
-        "SELECT id, username, password_hash, created_at FROM users WHERE username = ?"
-    )
-    .bind(&form.username)
-    .fetch_optional(&pool)
-    .await
-    .unwrap_or(None);
+    let pool = &state.pool;
+
+    let account_key = format!("account:{}", form.username.to_lowercase());
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[account_key]) {
+        let template = LoginTemplate::new(&state, Some(retry_message(retry_after)));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let user = db::user_by_username(pool, &form.username).await.log_err("Failed to look up user").flatten();
+
+    let ip = client.ip.map(|ip| ip.to_string());
+    let user_agent = user_agent(&headers);
+    let record = |user_id: Option<i64>, success: bool| {
+        db::record_login_attempt(pool, &form.username, user_id, ip.as_deref(), user_agent.as_deref(), success)
+    };
+
+    let locked = match &user {
+        Some(u) => lockout_remaining(pool, u.id).await,
+        None => None,
+    };
+
+    // A wrong password gets the same answer whether the account exists or is
+    // locked, so the lock can't be used to find usernames
+    // Accounts without a password, and missing ones, cost a hash all the same
+    let stored = match &user {
+        Some(u) if u.has_password() => u.password_hash.as_str(),
+        _ => dummy_hash(&state.config.passwords),
+    };
+    let valid = verify_password(&form.password, stored);
 
     let user = match user {
-        Some(u) if verify_password(&form.password, &u.password_hash) => u,
-        _ => {
-            let template = LoginTemplate {
-                error: Some("Invalid username or password".to_string()),
-            };
-            return Html(template.render().unwrap()).into_response();
+        Some(u) if valid && u.has_password() => u,
+        user => {
+            let user_id = user.as_ref().map(|u| u.id);
+            record(user_id, false).await.log_err("Failed to record sign-in attempt");
+            match user_id {
+                Some(_) if locked.is_some() => state.metrics.login_failed(LoginFailure::Locked),
+                Some(user_id) => {
+                    state.metrics.login_failed(LoginFailure::Password);
+                    apply_lockout(pool, &state.config.lockout, user_id).await;
+                }
+                None => state.metrics.login_failed(LoginFailure::Password),
+            }
+            let template = LoginTemplate::new(&state, Some("Invalid username or password".to_string()));
+            return render(&template).into_response();
         }
     };
 
-    let cookie = Cookie::build((SESSION_COOKIE, user.id.to_string()))
-        .path("/")
-        .http_only(true)
-        .build();
+    // Only someone who knows the password learns the account is locked
+    if let Some(remaining) = locked {
+        record(Some(user.id), false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::Locked);
+        return locked_response(&state, remaining);
+    }
+
+    if let Some(upgraded) = upgraded_hash(&state.config.passwords, &user.password_hash, &form.password) {
+        db::update_password(pool, user.id, &upgraded).await.log_err("Failed to store upgraded password hash");
+    }
+
+    if user.disabled {
+        record(Some(user.id), false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::Disabled);
+        return disabled_response(&state);
+    }
+
+    // Password is right; hold the session back until the second factor checks out
+    if user.totp_enabled {
+        return match start_two_factor_challenge(pool, jar, user.id).await {
+            Ok(jar) => (jar, Redirect::to("/login/2fa")).into_response(),
+            Err(e) => {
+                error!(error = %e, "Failed to start two-factor challenge");
+                let template = LoginTemplate::new(&state, Some("Failed to sign in".to_string()));
+                render(&template).into_response()
+            }
+        };
+    }
+
+    record(Some(user.id), true).await.log_err("Failed to record sign-in attempt");
+    db::clear_lockout(pool, user.id).await.log_err("Failed to clear lockout");
+
+    match start_session(pool, jar, user.id, &client, &headers).await {
+        Ok(jar) => (jar, Redirect::to("/dashboard")).into_response(),
+        Err(e) => {
+            error!(error = %e, "Failed to start session");
+            let template = LoginTemplate::new(&state, Some("Failed to sign in".to_string()));
+            render(&template).into_response()
+        }
+    }
+}
+
+pub async fn login_two_factor_page(jar: CookieJar) -> impl IntoResponse {
+    if jar.get(CHALLENGE_COOKIE).is_none() {
+        return Redirect::to("/login").into_response();
+    }
+    render(&LoginTwoFactorTemplate { error: None }).into_response()
+}
+
+pub async fn login_two_factor(
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
+    jar: CookieJar,
+    Form(form): Form<TwoFactorForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let expired = |jar: CookieJar| {
+        let template = LoginTemplate::new(&state, Some("Your sign-in expired. Please sign in again.".to_string()));
+        let cookie = Cookie::build((CHALLENGE_COOKIE, "")).path("/login").build();
+        (jar.remove(cookie), render(&template)).into_response()
+    };
+
+    let Some(token_hash) = jar.get(CHALLENGE_COOKIE).map(|c| hash_token(c.value())) else {
+        return Redirect::to("/login").into_response();
+    };
+    let Some((user_id, attempts)) = db::touch_login_challenge(pool, &token_hash)
+        .await
+        .log_err("Failed to look up sign-in challenge")
+        .flatten()
+    else {
+        return expired(jar);
+    };
+    let Some(user) = db::user_by_id(pool, user_id)
+        .await
+        .log_err("Failed to look up user")
+        .flatten()
+        .filter(|u| !u.disabled)
+    else {
+        return expired(jar);
+    };
+
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[format!("user:{}", user.id)]) {
+        let template = LoginTwoFactorTemplate {
+            error: Some(retry_message(retry_after)),
+        };
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let ip = client.ip.map(|ip| ip.to_string());
+    let user_agent = user_agent(&headers);
+    let record = |success: bool| {
+        db::record_login_attempt(pool, &user.username, Some(user.id), ip.as_deref(), user_agent.as_deref(), success)
+    };
+
+    if let Some(remaining) = lockout_remaining(pool, user.id).await {
+        db::delete_login_challenge(pool, &token_hash).await.log_err("Failed to delete sign-in challenge");
+        record(false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::Locked);
+        return locked_response(&state, remaining);
+    }
+
+    if !check_second_factor(pool, &user, &form.code).await {
+        record(false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::TwoFactor);
+        apply_lockout(pool, &state.config.lockout, user.id).await;
+        if attempts >= MAX_CHALLENGE_ATTEMPTS {
+            db::delete_login_challenge(pool, &token_hash).await.log_err("Failed to delete sign-in challenge");
+            return expired(jar);
+        }
+        let template = LoginTwoFactorTemplate {
+            error: Some("Invalid authentication code".to_string()),
+        };
+        return render(&template).into_response();
+    }
+
+    db::delete_login_challenge(pool, &token_hash).await.log_err("Failed to delete sign-in challenge");
+    record(true).await.log_err("Failed to record sign-in attempt");
+    db::clear_lockout(pool, user.id).await.log_err("Failed to clear lockout");
+
+    let jar = jar.remove(Cookie::build((CHALLENGE_COOKIE, "")).path("/login").build());
+    match start_session(pool, jar, user.id, &client, &headers).await {
+        Ok(jar) => (jar, Redirect::to("/dashboard")).into_response(),
+        Err(e) => {
+            error!(error = %e, "Failed to start session");
+            let template = LoginTemplate::new(&state, Some("Failed to sign in".to_string()));
+            render(&template).into_response()
+        }
+    }
+}
+
+/// Accept either a current TOTP code or an unused recovery code
+async fn check_second_factor(pool: &SqlitePool, user: &User, code: &str) -> bool {
+    if let Some(secret) = db::totp_secret(pool, user.id).await.log_err("Failed to read TOTP secret").flatten() {
+        if let Some(step) = twofactor::verify_code(&secret, code) {
+            return db::claim_totp_step(pool, user.id, step)
+                .await
+                .log_err("Failed to claim TOTP step")
+                .unwrap_or(false);
+        }
+    }
+
+    let recovery = twofactor::normalize_recovery_code(code);
+    !recovery.is_empty()
+        && db::use_recovery_code(pool, user.id, &hash_token(&recovery))
+            .await
+            .log_err("Failed to use recovery code")
+            .unwrap_or(false)
+}
 
-    (jar.add(cookie), Redirect::to("/dashboard")).into_response()
+pub async fn register_page(
+    State(state): State<AppState>,
+    Query(query): Query<RegisterQuery>,
+) -> impl IntoResponse {
+    if state.config.oidc.sso_only {
+        return Redirect::to("/login").into_response();
+    }
+    let invite = query.invite.trim().to_string();
+    render(&register_template(&state, None, invite)).into_response()
 }
 
-pub async fn register_page() -> impl IntoResponse {
-    Html(RegisterTemplate { error: None }.render().unwrap())
+/// The sign-up form with the fields the registration mode needs
+fn register_template(state: &AppState, error: Option<&str>, invite: String) -> RegisterTemplate {
+    let auth = &state.config.auth;
+    RegisterTemplate {
+        error: error.map(str::to_string),
+        closed: auth.registration == RegistrationMode::Closed,
+        invite_required: auth.registration == RegistrationMode::Invite,
+        invite,
+        username_patterns: auth.username_patterns.clone(),
+    }
 }
 
 pub async fn register(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
     jar: CookieJar,
     Form(form): Form<RegisterForm>,
 ) -> impl IntoResponse {
+    // Accounts come from the identity provider in SSO-only mode
+    if state.config.oidc.sso_only {
+        return Redirect::to("/login").into_response();
+    }
+    let pool = &state.pool;
+    let auth = &state.config.auth;
+    let invite = form.invite.trim().to_string();
+    let form_error = |status: StatusCode, msg: &str| {
+        let template = register_template(&state, Some(msg), invite.clone());
+        (status, render(&template)).into_response()
+    };
+
+    if auth.registration == RegistrationMode::Closed {
+        return form_error(StatusCode::FORBIDDEN, "Registration is closed");
+    }
+    if auth.registration == RegistrationMode::Invite && invite.is_empty() {
+        return form_error(StatusCode::FORBIDDEN, "An invite code is required to register");
+    }
+
     // Validate input
     if form.username.len() < 3 {
-        let template = RegisterTemplate {
-            error: Some("Username must be at least 3 characters".to_string()),
-        };
-        return Html(template.render().unwrap()).into_response();
+        return form_error(StatusCode::OK, "Username must be at least 3 characters");
+    }
+
+    if !auth.username_patterns.is_empty()
+        && !auth.username_patterns.iter().any(|p| matches_glob(p, &form.username))
+    {
+        return form_error(StatusCode::OK, "That username isn't allowed on this instance");
     }
 
     if form.password.len() < 6 {
-        let template = RegisterTemplate {
-            error: Some("Password must be at least 6 characters".to_string()),
-        };
-        return Html(template.render().unwrap()).into_response();
+        return form_error(StatusCode::OK, "Password must be at least 6 characters");
     }
 
     if form.password != form.confirm_password {
-        let template = RegisterTemplate {
-            error: Some("Passwords do not match".to_string()),
-        };
-        return Html(template.render().unwrap()).into_response();
+        return form_error(StatusCode::OK, "Passwords do not match");
     }
 
     // Check if username exists
     let exists: Option<(i64,)> = sqlx::query_as("SELECT id FROM users WHERE username = ?")
         .bind(&form.username)
-        .fetch_optional(&pool)
+        .fetch_optional(pool)
         .await
-        .unwrap_or(None);
+        .log_err("Failed to check username")
+        .flatten();
 
     if exists.is_some() {
-        let template = RegisterTemplate {
-            error: Some("Username already taken".to_string()),
-        };
-        return Html(template.render().unwrap()).into_response();
+        return form_error(StatusCode::OK, "Username already taken");
     }
 
     // Create user
-    let password_hash = match hash_password(&form.password) {
+    let password_hash = match hash_password(&form.password, &state.config.passwords) {
         Ok(h) => h,
-        Err(_) => {
-            let template = RegisterTemplate {
-                error: Some("Failed to create account".to_string()),
-            };
-            return Html(template.render().unwrap()).into_response();
+        Err(e) => {
+            error!(error = %e, "Failed to hash password");
+            return form_error(StatusCode::OK, "Failed to create account");
         }
     };
 
-    let result = sqlx::query("INSERT INTO users (username, password_hash) VALUES (?, ?)")
-        .bind(&form.username)
-        .bind(&password_hash)
-        .execute(&pool)
-        .await;
+    let redeem = (auth.registration == RegistrationMode::Invite).then_some(invite.as_str());
+    let user_id = match db::create_user(pool, &form.username, &password_hash, redeem).await {
+        Ok(Some(id)) => id,
+        Ok(None) => {
+            return form_error(StatusCode::FORBIDDEN, "That invite code is invalid, used up or expired");
+        }
+        Err(e) => {
+            error!(error = %e, "Failed to create user");
+            return form_error(StatusCode::OK, "Failed to create account");
+        }
+    };
 
-    match result {
-        Ok(r) => {
-            let user_id = r.last_insert_rowid();
-            let cookie = Cookie::build((SESSION_COOKIE, user_id.to_string()))
-                .path("/")
-                .http_only(true)
-                .build();
-            (jar.add(cookie), Redirect::to("/dashboard")).into_response()
-        }
-        Err(_) => {
-            let template = RegisterTemplate {
-                error: Some("Failed to create account".to_string()),
-            };
-            Html(template.render().unwrap()).into_response()
+    bootstrap_admin(&state, user_id, &form.username).await;
+    match start_session(pool, jar, user_id, &client, &headers).await {
+        Ok(jar) => (jar, Redirect::to("/dashboard")).into_response(),
+        Err(e) => {
+            error!(error = %e, "Failed to start session");
+            form_error(StatusCode::OK, "Failed to create account")
         }
     }
 }
 
-pub async fn logout(jar: CookieJar) -> impl IntoResponse {
+pub async fn logout(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    if let Some(session) = jar.get(SESSION_COOKIE) {
+        db::delete_session(&pool, &hash_token(session.value())).await.log_err("Failed to delete session");
+    }
+
     let cookie = Cookie::build((SESSION_COOKIE, ""))
         .path("/")
         .http_only(true)
@@ -244,184 +826,1712 @@ pub async fn logout(jar: CookieJar) -> impl IntoResponse {
     (jar.remove(cookie), Redirect::to("/"))
 }
 
-pub async fn dashboard(
-    State(pool): State<SqlitePool>,
+// =============================================================================
+// Single Sign-On Handlers
+// =============================================================================
+
+pub async fn sso_login(
+    State(state): State<AppState>,
     jar: CookieJar,
 ) -> impl IntoResponse {
-    let user = match get_current_user(&pool, &jar).await {
-        Some(u) => u,
-        None => return Redirect::to("/login").into_response(),
+    let Some(provider) = &state.sso else {
+        return AppError::NotFound.into_response();
+    };
+    let unavailable = || {
+        let template = LoginTemplate::new(&state, Some("Single sign-on is unavailable right now".to_string()));
+        (StatusCode::SERVICE_UNAVAILABLE, render(&template)).into_response()
+    };
+
+    let pending = match provider.begin().await {
+        Ok(p) => p,
+        Err(e) => {
+            error!(error = %e, "Failed to start SSO sign-in");
+            return unavailable();
+        }
     };
 
-    let pastes: Vec<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id 
-         FROM pastes WHERE user_id = ? ORDER BY created_at DESC LIMIT 50"
+    // State, nonce and PKCE verifier stay server-side, tied to this browser by a cookie
+    let token = generate_token();
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::minutes(OIDC_LOGIN_MINUTES);
+    let stored = db::create_oidc_login(
+        &state.pool,
+        &hash_token(&token),
+        &pending.state,
+        &pending.nonce,
+        &pending.pkce_verifier,
+        expires_at,
     )
-    .bind(user.id)
-    .fetch_all(&pool)
-    .await
-    .unwrap_or_default();
+    .await;
+    if stored.log_err("Failed to store SSO sign-in").is_none() {
+        return unavailable();
+    }
 
-    let template = DashboardTemplate { user, pastes };
-    Html(template.render().unwrap()).into_response()
+    let cookie = Cookie::build((OIDC_COOKIE, token))
+        .path("/login/oidc")
+        .http_only(true)
+        .same_site(SameSite::Lax)
+        .build();
+    (jar.add(cookie), Redirect::to(&pending.url)).into_response()
 }
 
-pub async fn public_pastes(
-    State(pool): State<SqlitePool>,
+pub async fn sso_callback(
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
     jar: CookieJar,
+    Query(query): Query<OidcCallbackQuery>,
 ) -> impl IntoResponse {
-    let user = get_current_user(&pool, &jar).await;
+    let pool = &state.pool;
+    let Some(provider) = &state.sso else {
+        return AppError::NotFound.into_response();
+    };
 
-    let pastes: Vec<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id 
-         FROM pastes 
-         WHERE password_hash IS NULL 
-         AND (expires_at IS NULL OR expires_at > datetime('now'))
-         ORDER BY created_at DESC LIMIT 50"
-    )
-    .fetch_all(&pool)
-    .await
-    .unwrap_or_default();
+    let login = match jar.get(OIDC_COOKIE) {
+        Some(c) => db::take_oidc_login(pool, &hash_token(c.value()))
+            .await
+            .log_err("Failed to look up SSO sign-in")
+            .flatten(),
+        None => None,
+    };
+    let jar = jar.remove(Cookie::build((OIDC_COOKIE, "")).path("/login/oidc").build());
+    let failed = |jar: CookieJar, error: &str| {
+        let template = LoginTemplate::new(&state, Some(error.to_string()));
+        (jar, render(&template)).into_response()
+    };
 
-    let template = PublicTemplate { user, pastes };
-    Html(template.render().unwrap()).into_response()
-}
+    let Some((expected_state, nonce, pkce_verifier)) = login else {
+        return failed(jar, "Your sign-in expired. Please sign in again.");
+    };
+    if query.error.is_some() {
+        return failed(jar, "Sign-in was cancelled or refused by the identity provider");
+    }
+    let (Some(code), Some(returned_state)) = (query.code, query.state) else {
+        return failed(jar, "Sign-in could not be verified. Please try again.");
+    };
+    if returned_state != expected_state {
+        return failed(jar, "Sign-in could not be verified. Please try again.");
+    }
 
-// =============================================================================
-// Paste Handlers
-// =============================================================================
+    let identity = match provider.finish(&code, &pkce_verifier, &nonce).await {
+        Ok(identity) => identity,
+        Err(e) => {
+            warn!(error = %e, "SSO sign-in failed");
+            return failed(jar, "Sign-in could not be verified. Please try again.");
+        }
+    };
 
-pub async fn index(
-    State(pool): State<SqlitePool>,
-    jar: CookieJar,
-) -> impl IntoResponse {
-    let user = get_current_user(&pool, &jar).await;
-    let template = IndexTemplate {
-        languages: get_supported_languages(),
-        user,
+    let user = match db::user_by_identity(pool, &identity.issuer, &identity.subject).await {
+        Ok(Some(user)) => user,
+        Ok(None) => match provision_sso_user(pool, &identity).await {
+            Ok(user) => {
+                bootstrap_admin(&state, user.id, &user.username).await;
+                user
+            }
+            Err(e) => {
+                error!(error = %e, "Failed to create SSO account");
+                return failed(jar, "Failed to create your account");
+            }
+        },
+        Err(e) => {
+            error!(error = %e, "Failed to look up SSO identity");
+            return failed(jar, "Failed to sign in");
+        }
     };
-    Html(template.render().unwrap())
-}
 
-pub async fn create_paste(
-    State(pool): State<SqlitePool>,
+    let ip = client.ip.map(|ip| ip.to_string());
+    let user_agent = user_agent(&headers);
+    let record = |success: bool| {
+        db::record_login_attempt(pool, &user.username, Some(user.id), ip.as_deref(), user_agent.as_deref(), success)
+    };
+
+    if let Some(remaining) = lockout_remaining(pool, user.id).await {
+        record(false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::Locked);
+        return locked_response(&state, remaining);
+    }
+    if user.disabled {
+        record(false).await.log_err("Failed to record sign-in attempt");
+        state.metrics.login_failed(LoginFailure::Disabled);
+        return disabled_response(&state);
+    }
+
+    if user.totp_enabled {
+        return match start_two_factor_challenge(pool, jar.clone(), user.id).await {
+            Ok(jar) => (jar, Redirect::to("/login/2fa")).into_response(),
+            Err(e) => {
+                error!(error = %e, "Failed to start two-factor challenge");
+                failed(jar, "Failed to sign in")
+            }
+        };
+    }
+
+    record(true).await.log_err("Failed to record sign-in attempt");
+    db::clear_lockout(pool, user.id).await.log_err("Failed to clear lockout");
+
+    match start_session(pool, jar.clone(), user.id, &client, &headers).await {
+        Ok(jar) => (jar, Redirect::to("/dashboard")).into_response(),
+        Err(e) => {
+            error!(error = %e, "Failed to start session");
+            failed(jar, "Failed to sign in")
+        }
+    }
+}
+
+/// Create an account for someone signing in through SSO for the first time.
+/// Accounts are linked by the provider's `sub` claim, never by username or email.
+async fn provision_sso_user(pool: &SqlitePool, identity: &oidc::Identity) -> Result<User, sqlx::Error> {
+    let base = sso_username(identity);
+
+    for attempt in 0..=MAX_USERNAME_ATTEMPTS {
+        let username = match attempt {
+            0 => base.clone(),
+            MAX_USERNAME_ATTEMPTS => format!("{}-{}", base, &generate_token()[..6]),
+            n => format!("{}-{}", base, n + 1),
+        };
+
+        match db::create_sso_user(pool, &username, &identity.issuer, &identity.subject).await {
+            Ok(user_id) => {
+                // A verified address from the provider is taken on if nobody else has it
+                let email = identity
+                    .email
+                    .as_deref()
+                    .filter(|_| identity.email_verified)
+                    .and_then(mail::normalize_address);
+                if let Some(email) = email {
+                    match db::set_email(pool, user_id, Some(&email)).await {
+                        Err(e) if !db::is_unique_violation(&e) => error!(error = %e, "Failed to store SSO email"),
+                        _ => {}
+                    }
+                }
+                return db::user_by_id(pool, user_id).await?.ok_or(sqlx::Error::RowNotFound);
+            }
+            Err(e) if db::is_unique_violation(&e) => {
+                // Either the username is taken or a concurrent login linked this identity
+                if let Some(user) = db::user_by_identity(pool, &identity.issuer, &identity.subject).await? {
+                    return Ok(user);
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(sqlx::Error::RowNotFound)
+}
+
+/// Username suggestion from the provider's claims, reduced to safe characters
+fn sso_username(identity: &oidc::Identity) -> String {
+    let claimed = identity
+        .preferred_username
+        .as_deref()
+        .or_else(|| identity.email.as_deref().and_then(|e| e.split('@').next()))
+        .unwrap_or_default();
+
+    let username: String = claimed
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        .take(32)
+        .collect();
+    if username.len() >= 3 {
+        username
+    } else {
+        "user".to_string()
+    }
+}
+
+// =============================================================================
+// Password Reset Handlers
+// =============================================================================
+
+pub async fn forgot_password_page(State(state): State<AppState>) -> impl IntoResponse {
+    let template = ForgotPasswordTemplate {
+        enabled: state.mailer.is_some(),
+        sent: false,
+        error: None,
+    };
+    render(&template)
+}
+
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    client: Client,
+    Form(form): Form<ForgotPasswordForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let Some(mailer) = state.mailer.clone() else {
+        return forgot_password_page(State(state)).await.into_response();
+    };
+
+    let account = form.account.trim();
+    let reset_key = format!("reset:{}", account.to_lowercase());
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[reset_key]) {
+        let template = ForgotPasswordTemplate {
+            enabled: true,
+            sent: false,
+            error: Some(retry_message(retry_after)),
+        };
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let user = if account.contains('@') {
+        match mail::normalize_address(account) {
+            Some(email) => db::user_by_email(pool, &email).await.log_err("Failed to look up user").flatten(),
+            None => None,
+        }
+    } else {
+        db::user_by_username(pool, account).await.log_err("Failed to look up user").flatten()
+    };
+
+    // The response is the same whether or not an account matched
+    if let Some((user, email)) = user.and_then(|u| u.email.clone().map(|e| (u, e))) {
+        let token = generate_token();
+        let ttl = chrono::Duration::from_std(state.config.mail.reset_token_ttl)
+            .unwrap_or_else(|_| chrono::Duration::hours(1));
+        let expires_at = chrono::Utc::now().naive_utc() + ttl;
+        if db::create_email_token(pool, &hash_token(&token), user.id, EmailTokenPurpose::Reset, None, expires_at)
+            .await
+            .log_err("Failed to create password reset token")
+            .is_some()
+        {
+            // Sent in the background so response time doesn't reveal a match
+            tokio::spawn(
+                async move {
+                    if let Err(e) = mailer.send_password_reset(&email, &user.username, &token).await {
+                        error!(error = %e, "Failed to send password reset email");
+                    }
+                }
+                .in_current_span(),
+            );
+        }
+    }
+
+    let template = ForgotPasswordTemplate {
+        enabled: true,
+        sent: true,
+        error: None,
+    };
+    render(&template).into_response()
+}
+
+pub async fn reset_password_page(
+    State(pool): State<SqlitePool>,
+    Query(query): Query<TokenQuery>,
+) -> impl IntoResponse {
+    let valid = db::email_token_valid(&pool, &hash_token(&query.token), EmailTokenPurpose::Reset)
+        .await
+        .log_err("Failed to check password reset token")
+        .unwrap_or(false);
+
+    let template = ResetPasswordTemplate {
+        token: valid.then_some(query.token),
+        done: false,
+        error: None,
+    };
+    render(&template)
+}
+
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Form(form): Form<ResetPasswordForm>,
+) -> impl IntoResponse {
+    let pool = state.pool;
+    let token_hash = hash_token(&form.token);
+    let page = |token: Option<String>, done: bool, error: Option<&str>| {
+        let template = ResetPasswordTemplate {
+            token,
+            done,
+            error: error.map(str::to_string),
+        };
+        render(&template)
+    };
+    let with_error = |token: String, error: &str| page(Some(token), false, Some(error));
+
+    let valid = db::email_token_valid(&pool, &token_hash, EmailTokenPurpose::Reset)
+        .await
+        .log_err("Failed to check password reset token")
+        .unwrap_or(false);
+    if !valid {
+        return page(None, false, None);
+    }
+    if form.password.len() < 6 {
+        return with_error(form.token, "Password must be at least 6 characters");
+    }
+    if form.password != form.confirm_password {
+        return with_error(form.token, "Passwords do not match");
+    }
+
+    let Some(password_hash) = hash_password(&form.password, &state.config.passwords).log_err("Failed to hash password")
+    else {
+        return with_error(form.token, "Failed to reset password");
+    };
+    let Some((user_id, _)) = db::claim_email_token(&pool, &token_hash, EmailTokenPurpose::Reset)
+        .await
+        .log_err("Failed to claim password reset token")
+        .flatten()
+    else {
+        return page(None, false, None);
+    };
+    if db::update_password(&pool, user_id, &password_hash).await.log_err("Failed to reset password").is_none() {
+        return with_error(form.token, "Failed to reset password");
+    }
+
+    // Whoever was signed in with the old password is signed out
+    db::revoke_all_sessions(&pool, user_id).await.log_err("Failed to revoke sessions");
+    db::delete_email_tokens(&pool, user_id).await.log_err("Failed to delete email tokens");
+    db::clear_lockout(&pool, user_id).await.log_err("Failed to clear lockout");
+
+    page(None, true, None)
+}
+
+pub async fn verify_email(
+    State(pool): State<SqlitePool>,
+    Query(query): Query<TokenQuery>,
+) -> impl IntoResponse {
+    let error = |code: StatusCode, title: &str, message: &str| {
+        let template = ErrorTemplate {
+            code: code.as_u16(),
+            title: title.to_string(),
+            message: message.to_string(),
+        };
+        (code, render(&template)).into_response()
+    };
+
+    let claimed = db::claim_email_token(&pool, &hash_token(&query.token), EmailTokenPurpose::Verify)
+        .await
+        .log_err("Failed to claim email confirmation token")
+        .flatten();
+    let Some((user_id, Some(email))) = claimed else {
+        return error(
+            StatusCode::BAD_REQUEST,
+            "Link Expired",
+            "This confirmation link is invalid or has expired. You can request a new one from your settings.",
+        );
+    };
+
+    match db::set_email(&pool, user_id, Some(&email)).await {
+        Ok(()) => Redirect::to("/settings").into_response(),
+        Err(e) if db::is_unique_violation(&e) => error(
+            StatusCode::CONFLICT,
+            "Email In Use",
+            "That email address already belongs to another account.",
+        ),
+        Err(e) => {
+            AppError::Database(e).into_response()
+        }
+    }
+}
+
+// =============================================================================
+// Two-Factor Handlers
+// =============================================================================
+
+pub async fn two_factor_page(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let user = match get_current_user(pool, &jar).await {
+        Some(u) => u,
+        None => return Redirect::to("/login").into_response(),
+    };
+    let required = state.config.auth.require_two_factor;
+
+    if user.totp_enabled {
+        let remaining_codes = db::remaining_recovery_codes(pool, user.id)
+            .await
+            .log_err("Failed to count recovery codes")
+            .unwrap_or(0);
+        let template = TwoFactorTemplate {
+            user,
+            required,
+            qr_svg: None,
+            secret: None,
+            recovery_codes: Vec::new(),
+            remaining_codes,
+            error: None,
+        };
+        return render(&template).into_response();
+    }
+
+    // Reloads and other tabs show the secret already scanned
+    let now = chrono::Utc::now().naive_utc();
+    let pending = db::pending_totp_secret(pool, user.id)
+        .await
+        .log_err("Failed to read TOTP secret")
+        .flatten()
+        .filter(|(_, created)| now - *created < chrono::Duration::hours(PENDING_TOTP_HOURS))
+        .map(|(secret, _)| secret);
+    let secret = match pending {
+        Some(secret) => secret,
+        None => match start_enrollment(pool, user.id).await {
+            Some(secret) => secret,
+            None => return AppError::Internal("Failed to start two-factor setup").into_response(),
+        },
+    };
+    render(&enrollment_template(user, required, secret, None)).into_response()
+}
+
+/// Throw away the pending secret and show a new one
+pub async fn restart_two_factor(State(state): State<AppState>, jar: CookieJar) -> impl IntoResponse {
+    let pool = &state.pool;
+    match get_current_user(pool, &jar).await {
+        Some(u) if !u.totp_enabled => {
+            if start_enrollment(pool, u.id).await.is_none() {
+                return AppError::Internal("Failed to start two-factor setup").into_response();
+            }
+            Redirect::to("/2fa").into_response()
+        }
+        Some(_) => Redirect::to("/2fa").into_response(),
+        None => Redirect::to("/login").into_response(),
+    }
+}
+
+async fn start_enrollment(pool: &SqlitePool, user_id: i64) -> Option<String> {
+    let secret = twofactor::generate_secret();
+    db::set_pending_totp_secret(pool, user_id, &secret).await.log_err("Failed to store TOTP secret")?;
+    Some(secret)
+}
+
+fn enrollment_template(user: User, required: bool, secret: String, error: Option<String>) -> TwoFactorTemplate {
+    let qr_svg = twofactor::provisioning_url(&secret, &user.username)
+        .and_then(|url| twofactor::qr_svg(&url));
+    TwoFactorTemplate {
+        user,
+        required,
+        qr_svg,
+        secret: Some(secret),
+        recovery_codes: Vec::new(),
+        remaining_codes: 0,
+        error,
+    }
+}
+
+/// Store fresh recovery codes and show them to the user once
+async fn issue_recovery_codes(pool: &SqlitePool, user: User, required: bool) -> Response {
+    let codes = twofactor::generate_recovery_codes();
+    let hashes: Vec<String> = codes
+        .iter()
+        .map(|c| hash_token(&twofactor::normalize_recovery_code(c)))
+        .collect();
+    if db::replace_recovery_codes(pool, user.id, &hashes).await.log_err("Failed to store recovery codes").is_none() {
+        return AppError::Internal("Failed to create recovery codes").into_response();
+    }
+
+    let template = TwoFactorTemplate {
+        user,
+        required,
+        qr_svg: None,
+        secret: None,
+        remaining_codes: codes.len() as i64,
+        recovery_codes: codes,
+        error: None,
+    };
+    render(&template).into_response()
+}
+
+pub async fn enable_two_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<TwoFactorForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let required = state.config.auth.require_two_factor;
+    let user = match get_current_user(pool, &jar).await {
+        Some(u) if !u.totp_enabled => u,
+        Some(_) => return Redirect::to("/2fa").into_response(),
+        None => return Redirect::to("/login").into_response(),
+    };
+    let Some(secret) = db::totp_secret(pool, user.id).await.log_err("Failed to read TOTP secret").flatten() else {
+        return Redirect::to("/2fa").into_response();
+    };
+
+    let confirmed = match twofactor::verify_code(&secret, &form.code) {
+        Some(step) => db::claim_totp_step(pool, user.id, step)
+            .await
+            .log_err("Failed to claim TOTP step")
+            .unwrap_or(false),
+        None => false,
+    };
+    if !confirmed {
+        let error = Some("That code didn't match. Check your authenticator app and try again.".to_string());
+        return render(&enrollment_template(user, required, secret, error)).into_response();
+    }
+
+    if db::enable_totp(pool, user.id).await.log_err("Failed to enable TOTP").is_none() {
+        return AppError::Internal("Failed to enable two-factor authentication").into_response();
+    }
+    issue_recovery_codes(pool, user, required).await
+}
+
+pub async fn regenerate_recovery_codes(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<TwoFactorForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let required = state.config.auth.require_two_factor;
+    let user = match get_current_user(pool, &jar).await {
+        Some(u) if u.totp_enabled => u,
+        Some(_) => return Redirect::to("/2fa").into_response(),
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    // Only an authenticator code will do here, not one of the old recovery codes
+    let secret = db::totp_secret(pool, user.id)
+        .await
+        .log_err("Failed to read TOTP secret")
+        .flatten()
+        .unwrap_or_default();
+    let valid = match twofactor::verify_code(&secret, &form.code) {
+        Some(step) => db::claim_totp_step(pool, user.id, step)
+            .await
+            .log_err("Failed to claim TOTP step")
+            .unwrap_or(false),
+        None => false,
+    };
+    if !valid {
+        let remaining_codes = db::remaining_recovery_codes(pool, user.id)
+            .await
+            .log_err("Failed to count recovery codes")
+            .unwrap_or(0);
+        let template = TwoFactorTemplate {
+            user,
+            required,
+            qr_svg: None,
+            secret: None,
+            recovery_codes: Vec::new(),
+            remaining_codes,
+            error: Some("Invalid authentication code".to_string()),
+        };
+        return render(&template).into_response();
+    }
+
+    issue_recovery_codes(pool, user, required).await
+}
+
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<DisableTwoFactorForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let required = state.config.auth.require_two_factor;
+    let user = match get_current_user(pool, &jar).await {
+        Some(u) if u.totp_enabled => u,
+        Some(_) => return Redirect::to("/2fa").into_response(),
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let error = if required {
+        Some("Two-factor authentication is required on this server")
+    } else if (user.has_password() && !verify_password(&form.password, &user.password_hash))
+        || !check_second_factor(pool, &user, &form.code).await
+    {
+        Some("Incorrect password or authentication code")
+    } else {
+        None
+    };
+
+    if let Some(error) = error {
+        let remaining_codes = db::remaining_recovery_codes(pool, user.id)
+            .await
+            .log_err("Failed to count recovery codes")
+            .unwrap_or(0);
+        let template = TwoFactorTemplate {
+            user,
+            required,
+            qr_svg: None,
+            secret: None,
+            recovery_codes: Vec::new(),
+            remaining_codes,
+            error: Some(error.to_string()),
+        };
+        return render(&template).into_response();
+    }
+
+    db::disable_totp(pool, user.id).await.log_err("Failed to disable TOTP");
+    Redirect::to("/dashboard").into_response()
+}
+
+// =============================================================================
+// Settings Handlers
+// =============================================================================
+
+async fn settings_template(state: &AppState, user: User, current_session: String) -> SettingsTemplate {
+    let pool = &state.pool;
+    let sessions = db::list_sessions(pool, user.id).await.log_err("Failed to list sessions").unwrap_or_default();
+    let tokens = db::list_api_tokens(pool, user.id).await.log_err("Failed to list API tokens").unwrap_or_default();
+    let pending_email = db::pending_email(pool, user.id).await.log_err("Failed to read pending email").flatten();
+    let can_invite = may_invite(state, &user);
+    let invites = if can_invite {
+        db::list_invites(pool, user.id).await.log_err("Failed to list invites").unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    SettingsTemplate {
+        user,
+        sessions,
+        current_session,
+        tokens,
+        new_token: None,
+        email_enabled: state.mailer.is_some(),
+        pending_email,
+        can_invite,
+        invites,
+        public_url: state.config.public_url.clone(),
+        notice: None,
+        error: None,
+    }
+}
+
+/// Admins can always invite; other users only if `INVITES_PER_USER` allows it
+fn may_invite(state: &AppState, user: &User) -> bool {
+    let auth = &state.config.auth;
+    auth.registration == RegistrationMode::Invite && (user.is_admin || auth.invites_per_user > 0)
+}
+
+async fn settings_error(state: &AppState, user: User, current_session: String, error: &str) -> Response {
+    let mut template = settings_template(state, user, current_session).await;
+    template.error = Some(error.to_string());
+    render(&template).into_response()
+}
+
+pub async fn settings_page(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let (user, current) = match current_session(&state.pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let template = settings_template(&state, user, current).await;
+    render(&template).into_response()
+}
+
+pub async fn change_password(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Form(form): Form<ChangePasswordForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let (user, current) = match current_session(pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[format!("user:{}", user.id)]) {
+        let mut template = settings_template(&state, user, current).await;
+        template.error = Some(retry_message(retry_after));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    if !user.has_password() {
+        return settings_error(&state, user, current, "This account signs in through single sign-on").await;
+    }
+    if !verify_password(&form.current_password, &user.password_hash) {
+        return settings_error(&state, user, current, "Current password is incorrect").await;
+    }
+    if form.new_password.len() < 6 {
+        return settings_error(&state, user, current, "Password must be at least 6 characters").await;
+    }
+    if form.new_password != form.confirm_password {
+        return settings_error(&state, user, current, "Passwords do not match").await;
+    }
+
+    let password_hash = match hash_password(&form.new_password, &state.config.passwords) {
+        Ok(h) => h,
+        Err(e) => {
+            error!(error = %e, "Failed to hash password");
+            return settings_error(&state, user, current, "Failed to change password").await;
+        }
+    };
+    if db::update_password(pool, user.id, &password_hash).await.log_err("Failed to change password").is_none() {
+        return settings_error(&state, user, current, "Failed to change password").await;
+    }
+
+    // Anyone else holding a session keeps it no longer than the old password
+    db::revoke_other_sessions(pool, user.id, &current).await.log_err("Failed to revoke other sessions");
+
+    let mut template = settings_template(&state, user, current).await;
+    template.notice = Some("Password changed. Your other sessions have been signed out.".to_string());
+    render(&template).into_response()
+}
+
+pub async fn revoke_session(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+    Form(form): Form<RevokeSessionForm>,
+) -> impl IntoResponse {
+    let Some((user, _)) = current_session(&pool, &jar).await else {
+        return Redirect::to("/login");
+    };
+
+    db::revoke_session(&pool, user.id, &form.session).await.log_err("Failed to revoke session");
+    Redirect::to("/settings")
+}
+
+pub async fn revoke_other_sessions(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let Some((user, current)) = current_session(&pool, &jar).await else {
+        return Redirect::to("/login");
+    };
+
+    db::revoke_other_sessions(&pool, user.id, &current).await.log_err("Failed to revoke other sessions");
+    Redirect::to("/settings")
+}
+
+pub async fn change_email(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Form(form): Form<EmailForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let (user, current) = match current_session(pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+    let Some(mailer) = &state.mailer else {
+        return settings_error(&state, user, current, "Email is not configured on this server").await;
+    };
+
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[format!("user:{}", user.id)]) {
+        let mut template = settings_template(&state, user, current).await;
+        template.error = Some(retry_message(retry_after));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let Some(email) = mail::normalize_address(&form.email) else {
+        return settings_error(&state, user, current, "Enter a valid email address").await;
+    };
+    if user.email.as_deref() == Some(email.as_str()) {
+        return settings_error(&state, user, current, "That is already your email address").await;
+    }
+
+    // The address only replaces the current one once its link is opened
+    let token = generate_token();
+    let expires_at = chrono::Utc::now().naive_utc() + chrono::Duration::hours(EMAIL_VERIFY_HOURS);
+    let stored = db::create_email_token(
+        pool,
+        &hash_token(&token),
+        user.id,
+        EmailTokenPurpose::Verify,
+        Some(&email),
+        expires_at,
+    )
+    .await;
+    if stored.log_err("Failed to create email confirmation token").is_none() {
+        return settings_error(&state, user, current, "Failed to update email").await;
+    }
+    if let Err(e) = mailer.send_verification(&email, &user.username, &token).await {
+        error!(error = %e, "Failed to send verification email");
+        return settings_error(&state, user, current, "Failed to send the confirmation email").await;
+    }
+
+    let mut template = settings_template(&state, user, current).await;
+    template.notice = Some(format!("We sent a confirmation link to {}.", email));
+    render(&template).into_response()
+}
+
+pub async fn remove_email(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let Some((user, _)) = current_session(&pool, &jar).await else {
+        return Redirect::to("/login");
+    };
+
+    db::set_email(&pool, user.id, None).await.log_err("Failed to remove email");
+    Redirect::to("/settings")
+}
+
+pub async fn create_api_token(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<CreateTokenForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let (user, current) = match current_session(pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    let name = form.name.trim();
+    if name.is_empty() || name.chars().count() > 64 {
+        return settings_error(&state, user, current, "Token name must be between 1 and 64 characters").await;
+    }
+
+    let token = generate_api_token();
+    if db::create_api_token(pool, user.id, name, &hash_token(&token))
+        .await
+        .log_err("Failed to create API token")
+        .is_none()
+    {
+        return settings_error(&state, user, current, "Failed to create token").await;
+    }
+
+    let mut template = settings_template(&state, user, current).await;
+    template.new_token = Some(token);
+    render(&template).into_response()
+}
+
+pub async fn delete_api_token(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let Some((user, _)) = current_session(&pool, &jar).await else {
+        return Redirect::to("/login");
+    };
+
+    db::delete_api_token(&pool, user.id, id).await.log_err("Failed to delete API token");
+    Redirect::to("/settings")
+}
+
+pub async fn create_invite(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Form(form): Form<CreateInviteForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let (user, current) = match current_session(pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    if !may_invite(&state, &user) {
+        return settings_error(&state, user, current, "You can't create invites").await;
+    }
+    if !(1..=MAX_INVITE_USES).contains(&form.uses) {
+        let msg = format!("An invite can be used between 1 and {} times", MAX_INVITE_USES);
+        return settings_error(&state, user, current, &msg).await;
+    }
+    let expiration = match form.expiration.parse::<Expiration>() {
+        Ok(exp) => exp,
+        Err(msg) => return settings_error(&state, user, current, msg).await,
+    };
+    let max_days = state.config.limits.max_expiration_days;
+    if expiration.minutes().is_some_and(|m| m > max_days * 24 * 60) {
+        let msg = format!("Expiration cannot be longer than {} days", max_days);
+        return settings_error(&state, user, current, &msg).await;
+    }
+    let expires_at = match expiration.to_datetime() {
+        Ok(at) => at,
+        Err(msg) => return settings_error(&state, user, current, msg).await,
+    };
+
+    let limit = state.config.auth.invites_per_user;
+    if !user.is_admin
+        && db::active_invite_count(pool, user.id)
+            .await
+            .log_err("Failed to count invites")
+            .unwrap_or(i64::MAX)
+            >= limit
+    {
+        let msg = format!("You can have at most {} unused invites at a time", limit);
+        return settings_error(&state, user, current, &msg).await;
+    }
+
+    let code = nanoid::nanoid!(INVITE_CODE_LENGTH, &INVITE_ALPHABET);
+    if db::create_invite(pool, user.id, &code, form.uses, expires_at).await.log_err("Failed to create invite").is_none() {
+        return settings_error(&state, user, current, "Failed to create invite").await;
+    }
+
+    let mut template = settings_template(&state, user, current).await;
+    template.notice = Some("Invite created. Share the link below with the person you're inviting.".to_string());
+    render(&template).into_response()
+}
+
+pub async fn delete_invite(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+    Path(id): Path<i64>,
+) -> impl IntoResponse {
+    let Some((user, _)) = current_session(&pool, &jar).await else {
+        return Redirect::to("/login");
+    };
+
+    db::delete_invite(&pool, user.id, id).await.log_err("Failed to delete invite");
+    Redirect::to("/settings")
+}
+
+pub async fn delete_account(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Form(form): Form<DeleteAccountForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let (user, current) = match current_session(pool, &jar).await {
+        Some(s) => s,
+        None => return Redirect::to("/login").into_response(),
+    };
+
+    if let Err(retry_after) = state.limiter.check(Bucket::Login, &client, &[format!("user:{}", user.id)]) {
+        let mut template = settings_template(&state, user, current).await;
+        template.error = Some(retry_message(retry_after));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let keep_pastes = match form.pastes.as_str() {
+        "orphan" => true,
+        "delete" => false,
+        _ => return settings_error(&state, user, current, "Choose what happens to your pastes").await,
+    };
+
+    // SSO accounts have no password, so they confirm by typing their username
+    let confirmed = if user.has_password() {
+        verify_password(&form.password, &user.password_hash)
+    } else {
+        form.confirm_username.trim() == user.username
+    };
+    let code = form.code.as_deref().unwrap_or_default();
+    if !confirmed || (user.totp_enabled && !check_second_factor(pool, &user, code).await) {
+        let msg = match (user.has_password(), user.totp_enabled) {
+            (true, true) => "Incorrect password or authentication code",
+            (true, false) => "Incorrect password",
+            (false, true) => "Username does not match or incorrect authentication code",
+            (false, false) => "Username does not match",
+        };
+        return settings_error(&state, user, current, msg).await;
+    }
+
+    if db::delete_user(pool, user.id, keep_pastes).await.log_err("Failed to delete account").is_none() {
+        return settings_error(&state, user, current, "Failed to delete account").await;
+    }
+    state.pastes.clear();
+
+    let cookie = Cookie::build((SESSION_COOKIE, ""))
+        .path("/")
+        .http_only(true)
+        .build();
+    (jar.remove(cookie), Redirect::to("/")).into_response()
+}
+
+pub async fn dashboard(
+    State(pool): State<SqlitePool>,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let user = match get_current_user(&pool, &jar).await {
+        Some(u) => u,
+        None => return Ok(Redirect::to("/login").into_response()),
+    };
+
+    let pastes: Vec<Paste> = sqlx::query_as(&format!(
+        "{} WHERE p.user_id = ? ORDER BY p.created_at DESC LIMIT 50",
+        db::PASTE_SELECT
+    ))
+    .bind(user.id)
+    .fetch_all(&pool)
+    .await?;
+
+    let sign_ins = db::recent_sign_ins(&pool, user.id, 10).await?;
+
+    let template = DashboardTemplate { user, pastes, sign_ins };
+    Ok(render(&template)?.into_response())
+}
+
+pub async fn public_pastes(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<Html<String>, AppError> {
+    let pool = &state.pool;
+    let user = get_current_user(pool, &jar).await;
+
+    let pastes: Vec<Paste> = sqlx::query_as(&format!(
+        "{} WHERE p.password_hash IS NULL 
+         AND p.hidden IS NULL
+         AND p.private = 0
+         AND (? OR p.user_id IS NOT NULL)
+         AND (p.expires_at IS NULL OR p.expires_at > datetime('now'))
+         ORDER BY p.created_at DESC LIMIT 50",
+        db::PASTE_SELECT
+    ))
+    .bind(state.config.spam.list_anonymous)
+    .fetch_all(pool)
+    .await?;
+
+    let template = PublicTemplate { user, pastes };
+    render(&template)
+}
+
+/// A proof-of-work challenge for scripts that create anonymous pastes
+pub async fn spam_challenge(State(state): State<AppState>) -> impl IntoResponse {
+    match state.spam.challenge() {
+        Some(challenge) => Json(challenge).into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+/// Stylesheets, scripts and fonts. Their names change with their content, so
+/// browsers may cache them for good.
+pub async fn static_file(Path(path): Path<String>) -> Response {
+    match assets::get(&path) {
+        Some(asset) => (
+            [
+                (header::CONTENT_TYPE, asset.content_type),
+                (header::CACHE_CONTROL, "public, max-age=31536000, immutable"),
+            ],
+            asset.body.as_slice(),
+        )
+            .into_response(),
+        None => AppError::NotFound.into_response(),
+    }
+}
+
+// =============================================================================
+// Admin Handlers
+// =============================================================================
+
+/// The signed-in user if they are an administrator. Everyone else gets a
+/// plain 404 so the admin area doesn't advertise itself.
+async fn require_admin(pool: &SqlitePool, jar: &CookieJar) -> Result<User, Response> {
+    match get_current_user(pool, jar).await {
+        Some(user) if user.is_admin => Ok(user),
+        _ => Err(AppError::NotFound.into_response()),
+    }
+}
+
+/// Record an admin action. A failed write is logged but doesn't undo the action.
+async fn audit(state: &AppState, admin: &User, client: &Client, action: &str, target: &str, details: Option<&str>) {
+    let ip = client.ip.map(|ip| ip.to_string());
+    if let Err(e) = db::record_audit(&state.pool, admin, action, target, details, ip.as_deref()).await {
+        error!(error = %e, action, target, "Failed to write audit log entry");
+    }
+}
+
+pub async fn admin_page(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> Result<Response, AppError> {
+    let user = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return Ok(response),
+    };
+
+    let stats = db::instance_stats(&state.pool).await?;
+    let audit_log = db::recent_audit_entries(&state.pool, AUDIT_LOG_ENTRIES).await?;
+
+    let template = AdminTemplate { user, stats, audit_log };
+    Ok(render(&template)?.into_response())
+}
+
+async fn admin_pastes_template(state: &AppState, user: User, query: String) -> AdminPastesTemplate {
+    let pastes = db::admin_search_pastes(&state.pool, query.trim())
+        .await
+        .log_err("Failed to search pastes")
+        .unwrap_or_default();
+    AdminPastesTemplate {
+        user,
+        query,
+        pastes,
+        notice: None,
+        error: None,
+    }
+}
+
+pub async fn admin_pastes(
+    State(state): State<AppState>,
     jar: CookieJar,
-    Form(form): Form<CreatePasteForm>,
+    Query(search): Query<AdminSearchQuery>,
 ) -> impl IntoResponse {
-    let user = get_current_user(&pool, &jar).await;
-    let user_id = user.map(|u| u.id);
-    
-    let id = generate_id();
-    let language = form.language.unwrap_or_else(|| "plaintext".to_string());
-    
-    let password_hash = match &form.password {
-        Some(pw) if !pw.is_empty() => hash_password(pw).ok(),
-        _ => None,
+    let user = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
     };
-    
-    let expires_at = form.expiration
-        .as_deref()
-        .map(Expiration::from_str)
-        .and_then(|exp| exp.to_datetime());
 
-    let result = sqlx::query(
-        "INSERT INTO pastes (id, content, language, password_hash, expires_at, user_id) VALUES (?, ?, ?, ?, ?, ?)"
-    )
-    .bind(&id)
-    .bind(&form.content)
-    .bind(&language)
-    .bind(&password_hash)
-    .bind(&expires_at)
-    .bind(&user_id)
-    .execute(&pool)
-    .await;
+    let template = admin_pastes_template(&state, user, search.q).await;
+    render(&template).into_response()
+}
 
-    match result {
-        Ok(_) => Redirect::to(&format!("/{}", id)).into_response(),
-        Err(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Failed to create paste").into_response(),
+/// Apply a moderation action to a paste: `delete`, `hide`, `unhide`, or
+/// `dismiss` its open reports. Returns the notice to show, or an error.
+async fn moderate_paste(
+    state: &AppState,
+    admin: &User,
+    client: &Client,
+    id: &str,
+    action: &str,
+) -> Result<String, String> {
+    let pool = &state.pool;
+    let Some(paste) = db::fetch_paste(pool, id).await.log_err("Failed to fetch paste").flatten() else {
+        return Err("Paste not found".to_string());
+    };
+    let failed = |e: sqlx::Error| {
+        error!(error = %e, paste = id, action, "Failed to moderate paste");
+        "Failed to update paste".to_string()
+    };
+
+    match action {
+        "delete" => {
+            // Note who owned the paste before it's gone
+            let owner = match paste.user_id {
+                Some(user_id) => db::user_by_id(pool, user_id)
+                    .await
+                    .log_err("Failed to look up paste owner")
+                    .flatten()
+                    .map(|u| u.username),
+                None => None,
+            };
+            db::delete_paste(pool, id).await.map_err(failed)?;
+            state.pastes.delete(id);
+            let details = format!("owner: {}", owner.as_deref().unwrap_or("anonymous"));
+            audit(state, admin, client, "paste.delete", id, Some(&details)).await;
+            Ok(format!("Paste {} deleted", id))
+        }
+        "hide" => {
+            db::set_hidden(pool, id, Some(HiddenState::Moderator)).await.map_err(failed)?;
+            state.pastes.remove(id);
+            let resolved = db::resolve_reports(pool, id, "hidden").await.log_err("Failed to resolve reports").unwrap_or(0);
+            let details = (resolved > 0).then(|| format!("resolved {} report(s)", resolved));
+            audit(state, admin, client, "paste.hide", id, details.as_deref()).await;
+            Ok(format!("Paste {} is now hidden from everyone but its owner", id))
+        }
+        "unhide" => {
+            db::set_hidden(pool, id, None).await.map_err(failed)?;
+            state.pastes.remove(id);
+            audit(state, admin, client, "paste.unhide", id, None).await;
+            Ok(format!("Paste {} is visible again", id))
+        }
+        "dismiss" => {
+            let resolved = db::resolve_reports(pool, id, "dismissed").await.map_err(failed)?;
+            // A paste only held back by reports goes back on the public list
+            if paste.hidden == Some(HiddenState::Review) {
+                db::set_hidden(pool, id, None).await.map_err(failed)?;
+                state.pastes.remove(id);
+            }
+            let details = format!("{} report(s)", resolved);
+            audit(state, admin, client, "report.dismiss", id, Some(&details)).await;
+            Ok(format!("Reports on {} dismissed", id))
+        }
+        _ => Err("Unknown action".to_string()),
     }
 }
 
-pub async fn view_paste(
-    State(pool): State<SqlitePool>,
+/// `delete`, `hide` or `unhide` from the paste search
+pub async fn admin_paste_action(
+    State(state): State<AppState>,
+    client: Client,
     jar: CookieJar,
-    Path(id): Path<String>,
+    Path((id, action)): Path<(String, String)>,
+    Form(form): Form<AdminActionForm>,
 ) -> impl IntoResponse {
-    let user = get_current_user(&pool, &jar).await;
-    
-    let paste: Option<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id 
-         FROM pastes WHERE id = ?"
-    )
-    .bind(&id)
-    .fetch_optional(&pool)
-    .await
-    .unwrap_or(None);
+    let admin = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let outcome = moderate_paste(&state, &admin, &client, &id, &action).await;
+
+    let mut template = admin_pastes_template(&state, admin, form.q).await;
+    match outcome {
+        Ok(notice) => template.notice = Some(notice),
+        Err(error) => template.error = Some(error),
+    }
+    render(&template).into_response()
+}
+
+async fn admin_reports_template(state: &AppState, user: User) -> AdminReportsTemplate {
+    let queue = db::report_queue(&state.pool).await.log_err("Failed to read report queue").unwrap_or_default();
+    AdminReportsTemplate {
+        user,
+        queue,
+        notice: None,
+        error: None,
+    }
+}
+
+pub async fn admin_reports(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let user = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let template = admin_reports_template(&state, user).await;
+    render(&template).into_response()
+}
+
+/// `dismiss`, `hide` or `delete` from the moderation queue
+pub async fn admin_report_action(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Path((id, action)): Path<(String, String)>,
+) -> impl IntoResponse {
+    let admin = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let outcome = moderate_paste(&state, &admin, &client, &id, &action).await;
+
+    let mut template = admin_reports_template(&state, admin).await;
+    match outcome {
+        Ok(notice) => template.notice = Some(notice),
+        Err(error) => template.error = Some(error),
+    }
+    render(&template).into_response()
+}
+
+async fn admin_users_template(state: &AppState, user: User, query: String) -> AdminUsersTemplate {
+    let users = db::admin_search_users(&state.pool, query.trim())
+        .await
+        .log_err("Failed to search users")
+        .unwrap_or_default();
+    AdminUsersTemplate {
+        user,
+        query,
+        users,
+        notice: None,
+        error: None,
+    }
+}
+
+pub async fn admin_users(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Query(search): Query<AdminSearchQuery>,
+) -> impl IntoResponse {
+    let user = match require_admin(&state.pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let template = admin_users_template(&state, user, search.q).await;
+    render(&template).into_response()
+}
+
+/// `disable`, `enable`, `grant-admin`, `revoke-admin` or `delete` on an account
+pub async fn admin_user_action(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Path((id, action)): Path<(i64, String)>,
+    Form(form): Form<AdminActionForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let admin = match require_admin(pool, &jar).await {
+        Ok(u) => u,
+        Err(response) => return response,
+    };
+
+    let outcome = match db::user_by_id(pool, id).await.log_err("Failed to look up user").flatten() {
+        None => Err("User not found".to_string()),
+        // Keep admins from locking themselves out
+        Some(target) if target.id == admin.id && action != "grant-admin" && action != "enable" => {
+            Err("You can't do that to your own account".to_string())
+        }
+        Some(target) => {
+            let name = target.username;
+            let (result, entry, details, notice) = match action.as_str() {
+                "disable" => (
+                    db::set_disabled(pool, id, true).await,
+                    "user.disable",
+                    None,
+                    format!("{} has been disabled and signed out", name),
+                ),
+                "enable" => (
+                    db::set_disabled(pool, id, false).await,
+                    "user.enable",
+                    None,
+                    format!("{} has been enabled", name),
+                ),
+                "grant-admin" => (
+                    db::set_admin(pool, id, true).await,
+                    "user.grant_admin",
+                    None,
+                    format!("{} is now an administrator", name),
+                ),
+                "revoke-admin" => (
+                    db::set_admin(pool, id, false).await,
+                    "user.revoke_admin",
+                    None,
+                    format!("{} is no longer an administrator", name),
+                ),
+                "delete" => {
+                    let keep_pastes = match form.pastes.as_deref() {
+                        Some("orphan") => true,
+                        Some("delete") => false,
+                        _ => {
+                            let mut template = admin_users_template(&state, admin, form.q).await;
+                            template.error = Some("Choose what happens to the user's pastes".to_string());
+                            return render(&template).into_response();
+                        }
+                    };
+                    (
+                        db::delete_user(pool, id, keep_pastes).await,
+                        "user.delete",
+                        Some(if keep_pastes { "pastes kept" } else { "pastes deleted" }),
+                        format!("{} has been deleted", name),
+                    )
+                }
+                _ => return AppError::NotFound.into_response(),
+            };
+
+            match result {
+                Ok(()) => {
+                    // Their pastes were deleted or lost their owner
+                    if entry == "user.delete" {
+                        state.pastes.clear();
+                    }
+                    audit(&state, &admin, &client, entry, &name, details).await;
+                    Ok(notice)
+                }
+                Err(e) => {
+                    error!(error = %e, user = %name, action = entry, "Failed to update user");
+                    Err("Failed to update user".to_string())
+                }
+            }
+        }
+    };
+
+    let mut template = admin_users_template(&state, admin, form.q).await;
+    match outcome {
+        Ok(notice) => template.notice = Some(notice),
+        Err(error) => template.error = Some(error),
+    }
+    render(&template).into_response()
+}
+
+// =============================================================================
+// Paste Handlers
+// =============================================================================
+
+pub async fn index(
+    State(state): State<AppState>,
+    jar: CookieJar,
+) -> impl IntoResponse {
+    let user = get_current_user(&state.pool, &jar).await;
+    let template = index_template(&state, user, CreatePasteForm::default());
+    render(&template)
+}
+
+/// The paste form, with a fresh proof-of-work challenge for anonymous visitors
+fn index_template(state: &AppState, user: Option<User>, draft: CreatePasteForm) -> IndexTemplate {
+    IndexTemplate {
+        languages: get_supported_languages(),
+        challenge: user.is_none().then(|| state.spam.challenge()).flatten(),
+        user,
+        error: None,
+        draft,
+        findings: Vec::new(),
+        secrets_blocked: false,
+    }
+}
+
+/// Re-render the paste form with the submitted values and an error
+fn index_with_error(
+    state: &AppState,
+    user: Option<User>,
+    draft: CreatePasteForm,
+    status: StatusCode,
+    error: &str,
+) -> Response {
+    let mut template = index_template(state, user, draft);
+    template.error = Some(error.to_string());
+    (status, render(&template)).into_response()
+}
+
+/// Re-render the paste form listing the likely secrets in it
+fn index_with_findings(
+    state: &AppState,
+    user: Option<User>,
+    draft: CreatePasteForm,
+    findings: Vec<Finding>,
+    blocked: bool,
+) -> Response {
+    let status = if blocked { StatusCode::UNPROCESSABLE_ENTITY } else { StatusCode::OK };
+    let error = blocked.then(|| secrets_blocked_message(user.as_ref()).to_string());
+    let mut template = index_template(state, user, draft);
+    template.error = error;
+    template.findings = findings;
+    template.secrets_blocked = blocked;
+    (status, render(&template)).into_response()
+}
+
+fn secrets_blocked_message(user: Option<&User>) -> &'static str {
+    if user.is_some() {
+        "This paste looks like it contains secrets. Store it as private or set a password to create it."
+    } else {
+        "This paste looks like it contains secrets. Set a password to create it."
+    }
+}
+
+/// Body of the JSON response to an API paste creation
+#[derive(Serialize)]
+struct CreatedPaste {
+    id: String,
+    url: String,
+    /// Likely secrets found in a public paste that was stored anyway
+    warnings: Vec<Finding>,
+}
+
+#[derive(Serialize)]
+struct SecretsRejected {
+    error: &'static str,
+    warnings: Vec<Finding>,
+}
+
+fn accepts(headers: &HeaderMap, media_type: &str) -> bool {
+    headers
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains(media_type))
+}
+
+pub async fn create_paste(
+    State(state): State<AppState>,
+    client: Client,
+    headers: HeaderMap,
+    jar: CookieJar,
+    form: Result<Form<CreatePasteForm>, FormRejection>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    let limits = &state.config.limits;
+    let user = match authenticated_user(pool, &jar, &headers).await {
+        Ok(user) => user,
+        Err(response) => return response,
+    };
+    let user_id = user.as_ref().map(|u| u.id);
+
+    let user_keys: Vec<String> = user_id.map(|id| format!("user:{}", id)).into_iter().collect();
+    if let Err(retry_after) = state.limiter.check(Bucket::CreatePaste, &client, &user_keys) {
+        let draft = form.map(|Form(f)| f).unwrap_or_default();
+        let mut template = index_template(&state, user, draft);
+        template.error = Some(retry_message(retry_after));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let form = match form {
+        Ok(Form(form)) => form,
+        Err(rejection) if rejection.status() == StatusCode::PAYLOAD_TOO_LARGE => {
+            let msg = format!("Paste is too large. The limit is {}.", format_bytes(limits.max_paste_bytes));
+            return index_with_error(&state, user, CreatePasteForm::default(), StatusCode::PAYLOAD_TOO_LARGE, &msg);
+        }
+        Err(rejection) => {
+            return index_with_error(&state, user, CreatePasteForm::default(), rejection.status(), &rejection.body_text());
+        }
+    };
+
+    if form.content.trim().is_empty() {
+        return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, "Paste content cannot be empty");
+    }
+
+    if form.content.len() > limits.max_paste_bytes {
+        let msg = format!("Paste is too large. The limit is {}.", format_bytes(limits.max_paste_bytes));
+        return index_with_error(&state, user, form, StatusCode::PAYLOAD_TOO_LARGE, &msg);
+    }
+
+    let language = match form.language.as_deref() {
+        None | Some("") => "plaintext".to_string(),
+        Some(lang) if get_supported_languages().iter().any(|(value, _)| *value == lang) => lang.to_string(),
+        Some(_) => {
+            return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, "Unsupported syntax language");
+        }
+    };
+
+    let expiration = match form.expiration.as_deref().unwrap_or("never").parse::<Expiration>() {
+        Ok(exp) => exp,
+        Err(msg) => return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, msg),
+    };
+    if expiration.minutes().is_some_and(|m| m > limits.max_expiration_days * 24 * 60) {
+        let msg = format!("Expiration cannot be longer than {} days", limits.max_expiration_days);
+        return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, &msg);
+    }
+    let expires_at = match expiration.to_datetime() {
+        Ok(at) => at,
+        Err(msg) => return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, msg),
+    };
+    let expiry = metrics::expiry_label(form.expiration.as_deref());
+
+    let slug = form.slug
+        .as_deref()
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(str::to_string);
+
+    if let Some(slug) = &slug {
+        if user.is_none() {
+            return index_with_error(&state, user, form, StatusCode::FORBIDDEN, "Log in to choose a custom URL");
+        }
+        if let Err(msg) = validate_slug(slug) {
+            return index_with_error(&state, user, form, StatusCode::BAD_REQUEST, msg);
+        }
+    }
+
+    // Signed-in users and API token holders are trusted
+    if user.is_none() {
+        let spam = state
+            .spam
+            .verify_work(form.pow_challenge.as_deref(), form.pow_nonce.as_deref())
+            .map_err(str::to_string)
+            .and_then(|_| state.spam.check(&form.content));
+        if let Err(reason) = spam {
+            let msg = format!("This paste was rejected by the spam filter. {}", reason);
+            return index_with_error(&state, user, form, StatusCode::UNPROCESSABLE_ENTITY, &msg);
+        }
+    }
 
-    let paste = match paste {
-        Some(p) => p,
-        None => return (StatusCode::NOT_FOUND, Html(NotFoundTemplate.render().unwrap())).into_response(),
+    // "Store as private" from the secrets warning
+    let private = form.private || form.secrets.as_deref() == Some("private");
+    if private && user.is_none() {
+        return index_with_error(&state, user, form, StatusCode::FORBIDDEN, "Log in to create private pastes");
+    }
+    let protected = form.password.as_deref().is_some_and(|pw| !pw.is_empty());
+
+    // Private and password-protected pastes are out of reach already
+    let findings = match &state.secrets {
+        Some(scanner) if !private && !protected => scanner.scan(&form.content),
+        _ => Vec::new(),
+    };
+    let wants_json = accepts(&headers, "application/json");
+    if !findings.is_empty() {
+        let browser = !wants_json && accepts(&headers, "text/html");
+        match state.config.secrets.policy {
+            SecretPolicy::Block if wants_json => {
+                let body = SecretsRejected { error: secrets_blocked_message(user.as_ref()), warnings: findings };
+                return (StatusCode::UNPROCESSABLE_ENTITY, Json(body)).into_response();
+            }
+            SecretPolicy::Block if browser => return index_with_findings(&state, user, form, findings, true),
+            SecretPolicy::Block => {
+                return (StatusCode::UNPROCESSABLE_ENTITY, secrets_blocked_message(user.as_ref())).into_response();
+            }
+            SecretPolicy::Warn if browser && form.secrets.as_deref() != Some("ignore") => {
+                return index_with_findings(&state, user, form, findings, false);
+            }
+            _ => {}
+        }
+    }
+
+    let password_hash = match &form.password {
+        Some(pw) if !pw.is_empty() => hash_password(pw, &state.config.passwords).log_err("Failed to hash paste password"),
+        _ => None,
     };
 
-    // Check expiration
-    if let Some(expires_at) = paste.expires_at {
-        if expires_at < chrono::Utc::now().naive_utc() {
-            let _ = sqlx::query("DELETE FROM pastes WHERE id = ?")
-                .bind(&id)
-                .execute(&pool)
-                .await;
-            return (StatusCode::NOT_FOUND, Html(NotFoundTemplate.render().unwrap())).into_response();
+    // Random IDs are retried on collision; a taken vanity slug is reported back
+    let mut attempts = 0;
+    loop {
+        let id = slug.clone().unwrap_or_else(|| generate_id(&state.config.ids));
+
+        let result = db::insert_paste(pool, &NewPaste {
+            id: &id,
+            content: &form.content,
+            language: &language,
+            password_hash: password_hash.as_deref(),
+            expires_at,
+            user_id,
+            private,
+        })
+        .await;
+        if result.is_ok() {
+            state.metrics.paste_created(&language, expiry);
+        }
+
+        match result {
+            Ok(_) if wants_json => {
+                let url = format!("{}/{}", state.config.public_url, id);
+                let body = CreatedPaste { id, url: url.clone(), warnings: findings };
+                return (StatusCode::CREATED, [(header::LOCATION, url)], Json(body)).into_response();
+            }
+            Ok(_) if !findings.is_empty() => {
+                return (
+                    [(HeaderName::from_static("x-secrets-detected"), findings.len().to_string())],
+                    Redirect::to(&format!("/{}", id)),
+                )
+                    .into_response();
+            }
+            Ok(_) => return Redirect::to(&format!("/{}", id)).into_response(),
+            Err(e) if db::is_unique_violation(&e) => {
+                if slug.is_some() {
+                    return index_with_error(&state, user, form, StatusCode::CONFLICT, "That custom URL is already taken");
+                }
+                attempts += 1;
+                if attempts >= MAX_ID_ATTEMPTS {
+                    error!(attempts, "No free paste ID found");
+                    return AppError::Internal("Failed to create paste").into_response();
+                }
+            }
+            Err(e) => return AppError::Database(e).into_response(),
         }
     }
+}
+
+pub async fn view_paste(
+    State(state): State<AppState>,
+    jar: CookieJar,
+    Path(id): Path<String>,
+) -> Result<Html<String>, AppError> {
+    let pool = &state.pool;
+    let user = get_current_user(pool, &jar).await;
+    
+    let entry = state.pastes.get(pool, &id).await?.ok_or(AppError::NotFound)?;
+    let paste = &entry.paste;
 
     // Password protected - check if owner
     let is_owner = user.as_ref().map(|u| Some(u.id) == paste.user_id).unwrap_or(false);
+
+    if !can_see(paste, user.as_ref()) {
+        return Err(AppError::NotFound);
+    }
     
     if paste.password_hash.is_some() && !is_owner {
         let template = PasswordTemplate { id, error: None };
-        return Html(template.render().unwrap()).into_response();
+        return render(&template);
     }
 
-    // Increment view count
-    let _ = sqlx::query("UPDATE pastes SET view_count = view_count + 1 WHERE id = ?")
-        .bind(&paste.id)
-        .execute(&pool)
-        .await;
+    state.pastes.count_view(&entry);
+    state.metrics.paste_viewed();
 
-    let formatted_date = paste.created_at.format("%Y-%m-%d %H:%M").to_string();
-    let expires_in = calculate_expires_in(paste.expires_at);
+    render(&view_template(&entry, user))
+}
 
-    let template = ViewTemplate {
+fn view_template(entry: &CachedPaste, user: Option<User>) -> ViewTemplate<'_> {
+    let paste = &entry.paste;
+    let is_owner = user.as_ref().map(|u| Some(u.id) == paste.user_id).unwrap_or(false);
+    ViewTemplate {
         paste,
-        formatted_date,
-        expires_in,
+        code_html: &entry.code_html,
+        lines: entry.lines,
+        views: entry.views(),
+        formatted_date: paste.created_at.format("%Y-%m-%d %H:%M").to_string(),
+        expires_in: calculate_expires_in(paste.expires_at),
         user,
         is_owner,
-    };
-    Html(template.render().unwrap()).into_response()
+    }
 }
 
 pub async fn verify_paste_password(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    client: Client,
     jar: CookieJar,
     Path(id): Path<String>,
     Form(form): Form<PasswordForm>,
 ) -> impl IntoResponse {
+    let pool = state.pool;
+
+    let paste_key = format!("paste:{}", id);
+    if let Err(retry_after) = state.limiter.check(Bucket::Unlock, &client, &[paste_key]) {
+        let template = PasswordTemplate {
+            id,
+            error: Some(retry_message(retry_after)),
+        };
+        return too_many_requests(retry_after, render(&template));
+    }
+
     let user = get_current_user(&pool, &jar).await;
     
-    let paste: Option<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id 
-         FROM pastes WHERE id = ?"
-    )
-    .bind(&id)
-    .fetch_optional(&pool)
-    .await
-    .unwrap_or(None);
-
-    let paste = match paste {
-        Some(p) => p,
-        None => return (StatusCode::NOT_FOUND, Html(NotFoundTemplate.render().unwrap())).into_response(),
+    let entry = match state.pastes.get(&pool, &id).await {
+        Ok(Some(entry)) if can_see(&entry.paste, user.as_ref()) => entry,
+        Ok(_) => return AppError::NotFound.into_response(),
+        Err(e) => return AppError::from(e).into_response(),
     };
+    let paste = &entry.paste;
 
     let is_valid = paste.password_hash
         .as_ref()
@@ -429,89 +2539,219 @@ pub async fn verify_paste_password(
         .unwrap_or(false);
 
     if !is_valid {
+        state.metrics.unlock_failed();
         let template = PasswordTemplate {
             id,
             error: Some("Incorrect password".to_string()),
         };
-        return Html(template.render().unwrap()).into_response();
+        return render(&template).into_response();
     }
 
-    let _ = sqlx::query("UPDATE pastes SET view_count = view_count + 1 WHERE id = ?")
-        .bind(&paste.id)
-        .execute(&pool)
-        .await;
+    let upgraded = paste
+        .password_hash
+        .as_deref()
+        .and_then(|h| upgraded_hash(&state.config.passwords, h, &form.password));
+    if let Some(upgraded) = upgraded {
+        db::update_paste_password(&pool, &paste.id, &upgraded).await.log_err("Failed to upgrade paste password hash");
+        state.pastes.remove(&paste.id);
+    }
 
-    let formatted_date = paste.created_at.format("%Y-%m-%d %H:%M").to_string();
-    let expires_in = calculate_expires_in(paste.expires_at);
-    let is_owner = user.as_ref().map(|u| Some(u.id) == paste.user_id).unwrap_or(false);
+    state.pastes.count_view(&entry);
+    state.metrics.paste_viewed();
 
-    let template = ViewTemplate {
-        paste,
-        formatted_date,
-        expires_in,
-        user,
-        is_owner,
-    };
-    Html(template.render().unwrap()).into_response()
+    render(&view_template(&entry, user)).into_response()
 }
 
 pub async fn view_raw(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    jar: CookieJar,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    let paste: Option<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id 
-         FROM pastes WHERE id = ?"
-    )
-    .bind(&id)
-    .fetch_optional(&pool)
-    .await
-    .unwrap_or(None);
+) -> Result<Response, AppError> {
+    let user = get_current_user(&state.pool, &jar).await;
+    let entry = state.pastes.get(&state.pool, &id).await?.filter(|e| can_see(&e.paste, user.as_ref()));
 
-    match paste {
+    match entry.as_deref().map(|e| &e.paste) {
         Some(p) if p.password_hash.is_none() => {
-            (StatusCode::OK, [("content-type", "text/plain; charset=utf-8")], p.content).into_response()
+            let headers = [
+                (header::CONTENT_TYPE, "text/plain; charset=utf-8"),
+                (header::CONTENT_SECURITY_POLICY, RAW_CSP),
+            ];
+            Ok((StatusCode::OK, headers, p.content.clone()).into_response())
         }
-        Some(_) => (StatusCode::FORBIDDEN, "This paste is password protected").into_response(),
-        None => (StatusCode::NOT_FOUND, "Paste not found").into_response(),
+        Some(_) => Err(AppError::Forbidden("This paste is password protected")),
+        None => Err(AppError::NotFound),
     }
 }
 
 pub async fn delete_paste(
-    State(pool): State<SqlitePool>,
+    State(state): State<AppState>,
+    headers: HeaderMap,
     jar: CookieJar,
     Path(id): Path<String>,
-) -> impl IntoResponse {
-    let user = get_current_user(&pool, &jar).await;
+) -> Result<Response, AppError> {
+    let pool = &state.pool;
+    let user = match authenticated_user(pool, &jar, &headers).await {
+        Ok(user) => user,
+        Err(response) => return Ok(response),
+    };
     
     // Only allow deletion by owner
-    let paste: Option<Paste> = sqlx::query_as(
-        "SELECT id, content, language, password_hash, expires_at, created_at, view_count, user_id FROM pastes WHERE id = ?"
-    )
-    .bind(&id)
-    .fetch_optional(&pool)
-    .await
-    .unwrap_or(None);
+    let paste = db::fetch_paste(pool, &id).await?;
 
     if let Some(paste) = paste {
         let is_owner = user.as_ref().map(|u| Some(u.id) == paste.user_id).unwrap_or(false);
         let is_anonymous = paste.user_id.is_none();
         
         if is_owner || is_anonymous {
-            let _ = sqlx::query("DELETE FROM pastes WHERE id = ?")
-                .bind(&id)
-                .execute(&pool)
-                .await;
+            db::delete_paste(pool, &id).await?;
+            state.pastes.delete(&id);
+        }
+    }
+
+    Ok(Redirect::to("/").into_response())
+}
+
+/// Private pastes and those taken down by a moderator are visible to their
+/// owner and admins only
+fn can_see(paste: &Paste, user: Option<&User>) -> bool {
+    (!paste.private && paste.hidden != Some(HiddenState::Moderator))
+        || user.is_some_and(|u| u.is_admin || Some(u.id) == paste.user_id)
+}
+
+/// A paste that can be reported: it exists, hasn't expired, isn't private and
+/// isn't taken down
+async fn reportable_paste(pool: &SqlitePool, id: &str) -> Option<Paste> {
+    let paste = db::fetch_paste(pool, id).await.log_err("Failed to fetch paste")??;
+    let expired = paste.expires_at.is_some_and(|e| e < chrono::Utc::now().naive_utc());
+    (!expired && !paste.private && paste.hidden != Some(HiddenState::Moderator)).then_some(paste)
+}
+
+pub async fn report_page(
+    State(pool): State<SqlitePool>,
+    Path(id): Path<String>,
+) -> impl IntoResponse {
+    if reportable_paste(&pool, &id).await.is_none() {
+        return AppError::NotFound.into_response();
+    }
+
+    let template = ReportTemplate {
+        id,
+        reasons: ReportReason::ALL,
+        sent: false,
+        error: None,
+    };
+    render(&template).into_response()
+}
+
+pub async fn report_paste(
+    State(state): State<AppState>,
+    client: Client,
+    jar: CookieJar,
+    Path(id): Path<String>,
+    Form(form): Form<ReportForm>,
+) -> impl IntoResponse {
+    let pool = &state.pool;
+    if reportable_paste(pool, &id).await.is_none() {
+        return AppError::NotFound.into_response();
+    }
+
+    let mut template = ReportTemplate {
+        id,
+        reasons: ReportReason::ALL,
+        sent: false,
+        error: None,
+    };
+
+    if let Err(retry_after) = state.limiter.check(Bucket::Report, &client, &[]) {
+        template.error = Some(retry_message(retry_after));
+        return too_many_requests(retry_after, render(&template));
+    }
+
+    let reason = match form.reason.parse::<ReportReason>() {
+        Ok(r) => r,
+        Err(e) => {
+            template.error = Some(e.to_string());
+            return (StatusCode::BAD_REQUEST, render(&template)).into_response();
+        }
+    };
+    let details: String = form.details.trim().chars().take(MAX_REPORT_DETAILS).collect();
+
+    let user = get_current_user(pool, &jar).await;
+    let ip = client.ip.map(|ip| ip.to_string());
+    let filed = db::create_report(
+        pool,
+        &template.id,
+        reason,
+        Some(details.as_str()).filter(|d| !d.is_empty()),
+        user.map(|u| u.id),
+        ip.as_deref(),
+    )
+    .await;
+
+    match filed {
+        Ok(true) => {
+            let threshold = state.config.moderation.report_threshold;
+            let open = db::open_report_count(pool, &template.id).await.log_err("Failed to count reports").unwrap_or(0);
+            if threshold > 0
+                && open >= i64::from(threshold)
+                && db::hide_for_review(pool, &template.id)
+                    .await
+                    .log_err("Failed to hide paste for review")
+                    .unwrap_or(false)
+            {
+                state.pastes.remove(&template.id);
+                info!(paste = %template.id, reports = open, "Paste hidden from the public list for review");
+            }
+        }
+        // Already reported by this visitor; thank them all the same
+        Ok(false) => {}
+        Err(e) => {
+            error!(error = %e, "Failed to file report");
+            template.error = Some("Failed to send report".to_string());
+            return render(&template).into_response();
         }
     }
 
-    Redirect::to("/").into_response()
+    template.sent = true;
+    render(&template).into_response()
 }
 
 // =============================================================================
 // Helpers
 // =============================================================================
 
+fn too_many_requests(retry_after: u64, body: impl IntoResponse) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(header::RETRY_AFTER, retry_after.to_string())],
+        body,
+    )
+        .into_response()
+}
+
+fn retry_message(retry_after: u64) -> String {
+    format!("Too many attempts. Try again in {}.", format_wait(retry_after))
+}
+
+fn format_wait(secs: u64) -> String {
+    match secs {
+        0..=1 => "1 second".to_string(),
+        2..=60 => format!("{} seconds", secs),
+        61..=120 => "2 minutes".to_string(),
+        _ => format!("{} minutes", secs.div_ceil(60)),
+    }
+}
+
+fn format_bytes(bytes: usize) -> String {
+    if bytes >= 1024 * 1024 {
+        format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+    } else if bytes >= 1024 {
+        format!("{:.1} KB", bytes as f64 / 1024.0)
+    } else {
+        format!("{} bytes", bytes)
+    }
+}
+
 fn calculate_expires_in(expires_at: Option<chrono::NaiveDateTime>) -> Option<String> {
     let expires = expires_at?;
     let now = chrono::Utc::now().naive_utc();
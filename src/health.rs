@@ -0,0 +1,74 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use axum::{
+    extract::State,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::Serialize;
+use tracing::warn;
+
+use crate::db;
+use crate::state::AppState;
+use crate::tasks::CLEANUP_INTERVAL;
+
+/// A database that takes longer than this to answer counts as unavailable
+const DATABASE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The cleanup task beats on every run; two missed runs mean it is stuck
+const CLEANUP_GRACE: Duration = Duration::from_secs(60);
+
+#[derive(Serialize)]
+struct Readiness {
+    status: &'static str,
+    /// `ok`, or what is wrong
+    checks: BTreeMap<&'static str, String>,
+}
+
+/// Liveness: the process is up and answering requests
+pub async fn healthz() -> &'static str {
+    "ok"
+}
+
+/// Readiness: the database answers, its schema is complete and background
+/// cleanup is running. 503 with the failed checks otherwise.
+pub async fn readyz(State(state): State<AppState>) -> Response {
+    let mut checks = BTreeMap::new();
+
+    let (database, schema) = match tokio::time::timeout(DATABASE_TIMEOUT, db::missing_tables(&state.pool)).await {
+        Ok(Ok(missing)) if missing.is_empty() => (Ok(()), Ok(())),
+        Ok(Ok(missing)) => (Ok(()), Err(format!("missing tables: {}", missing.join(", ")))),
+        // The database answered, so a failing query means outdated columns
+        Ok(Err(sqlx::Error::Database(e))) => (Ok(()), Err(e.to_string())),
+        Ok(Err(e)) => (Err(e.to_string()), Err("unknown".to_string())),
+        Err(_) => (
+            Err(format!("no answer within {}s", DATABASE_TIMEOUT.as_secs())),
+            Err("unknown".to_string()),
+        ),
+    };
+    checks.insert("database", database);
+    checks.insert("schema", schema);
+
+    let since = state.cleanup.age();
+    let cleanup = if since > CLEANUP_INTERVAL * 2 + CLEANUP_GRACE {
+        Err(format!("last ran {}s ago", since.as_secs()))
+    } else {
+        Ok(())
+    };
+    checks.insert("cleanup", cleanup);
+
+    let ready = checks.values().all(Result::is_ok);
+    let checks: BTreeMap<&'static str, String> = checks
+        .into_iter()
+        .map(|(name, result)| (name, result.err().unwrap_or_else(|| "ok".to_string())))
+        .collect();
+
+    if !ready {
+        warn!(checks = %serde_json::to_string(&checks).unwrap_or_default(), "Not ready");
+        let body = Readiness { status: "unavailable", checks };
+        return (StatusCode::SERVICE_UNAVAILABLE, Json(body)).into_response();
+    }
+    Json(Readiness { status: "ready", checks }).into_response()
+}
@@ -0,0 +1,154 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rustls_pki_types::{CertificateDer, ServerName, UnixTime};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_rustls::rustls::{
+    client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+    crypto::{self, ring, CryptoProvider},
+    ClientConfig, DigitallySignedStruct, SignatureScheme,
+};
+use tokio_rustls::TlsConnector;
+
+use crate::config::{Config, ListenAddr};
+
+/// The whole probe, connecting included, has to finish within this
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `oxide-paste --healthcheck`: ask the running server for `/healthz` on the
+/// first `LISTEN` address, over TLS when the server terminates it. Meant for
+/// container health checks, which run in the same environment as the server.
+pub async fn run(config: &Config) -> Result<(), String> {
+    let addr = config
+        .listen
+        .addrs
+        .first()
+        .ok_or_else(|| "LISTEN has no addresses".to_string())?;
+
+    tokio::time::timeout(TIMEOUT, probe(addr, config.tls.is_some()))
+        .await
+        .map_err(|_| format!("no answer within {}s", TIMEOUT.as_secs()))?
+}
+
+async fn probe(addr: &ListenAddr, tls: bool) -> Result<(), String> {
+    match addr {
+        ListenAddr::Tcp(addr) => {
+            let addr = SocketAddr::new(local_ip(addr.ip()), addr.port());
+            let stream = TcpStream::connect(addr).await.map_err(|e| format!("{}: {}", addr, e))?;
+            if !tls {
+                return get_healthz(stream).await;
+            }
+            let connector = TlsConnector::from(Arc::new(tls_config()?));
+            let name = ServerName::IpAddress(addr.ip().into());
+            let stream = connector
+                .connect(name, stream)
+                .await
+                .map_err(|e| format!("TLS handshake failed: {}", e))?;
+            get_healthz(stream).await
+        }
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => {
+            let stream = tokio::net::UnixStream::connect(path)
+                .await
+                .map_err(|e| format!("{}: {}", path.display(), e))?;
+            get_healthz(stream).await
+        }
+        #[cfg(not(unix))]
+        ListenAddr::Unix(_) => Err("Unix sockets are not supported on this platform".to_string()),
+    }
+}
+
+/// A wildcard bind address is reached through loopback
+fn local_ip(ip: IpAddr) -> IpAddr {
+    match ip {
+        IpAddr::V4(ip) if ip.is_unspecified() => Ipv4Addr::LOCALHOST.into(),
+        IpAddr::V6(ip) if ip.is_unspecified() => Ipv6Addr::LOCALHOST.into(),
+        ip => ip,
+    }
+}
+
+async fn get_healthz<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S) -> Result<(), String> {
+    stream
+        .write_all(b"GET /healthz HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+        .await
+        .map_err(|e| format!("Failed to send request: {}", e))?;
+
+    // The status line is all we need
+    let mut head = [0u8; 64];
+    let mut read = 0;
+    while read < head.len() {
+        match stream.read(&mut head[read..]).await {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(e) => return Err(format!("Failed to read response: {}", e)),
+        }
+        if head[..read].contains(&b'\n') {
+            break;
+        }
+    }
+
+    let status = String::from_utf8_lossy(&head[..read]);
+    let status = status.lines().next().unwrap_or_default();
+    match status.split_whitespace().nth(1) {
+        Some("200") => Ok(()),
+        _ => Err(format!("unexpected response `{}`", status)),
+    }
+}
+
+// =============================================================================
+// TLS
+// =============================================================================
+
+fn tls_config() -> Result<ClientConfig, String> {
+    let provider = Arc::new(ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(AnyCertificate(provider)))
+        .with_no_client_auth();
+    Ok(config)
+}
+
+/// The certificate is for the public host name, not the loopback address the
+/// probe connects to, and the probe only asks whether the server answers. The
+/// handshake signatures are still checked.
+#[derive(Debug)]
+struct AnyCertificate(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for AnyCertificate {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, tokio_rustls::rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.0.signature_verification_algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
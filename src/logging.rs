@@ -0,0 +1,99 @@
+use std::fmt;
+use std::time::Instant;
+
+use axum::{
+    extract::{MatchedPath, Request},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use tracing::Instrument;
+use tracing_subscriber::EnvFilter;
+
+use crate::config::{LogConfig, LogFormat};
+
+// =============================================================================
+// Setup
+// =============================================================================
+
+/// Send `tracing` events to stdout, as text or JSON lines
+pub fn init(config: &LogConfig) -> Result<(), String> {
+    let filter = EnvFilter::try_new(&config.filter).map_err(|e| format!("LOG_LEVEL is invalid: {}", e))?;
+    let logger = tracing_subscriber::fmt().with_env_filter(filter).with_ansi(false);
+
+    let result = match config.format {
+        LogFormat::Text => logger.try_init(),
+        // Span fields go under `spans`, outermost first
+        LogFormat::Json => logger.json().flatten_event(true).with_current_span(false).with_span_list(true).try_init(),
+    };
+    result.map_err(|e| format!("Failed to set up logging: {}", e))
+}
+
+/// Logs the error of a `Result` that a handler falls back from instead of
+/// failing the request
+pub trait LogErr<T> {
+    /// The `Ok` value, or `None` after logging the error under `context`
+    fn log_err(self, context: &str) -> Option<T>;
+}
+
+impl<T, E: fmt::Display> LogErr<T> for Result<T, E> {
+    #[track_caller]
+    fn log_err(self, context: &str) -> Option<T> {
+        match self {
+            Ok(value) => Some(value),
+            Err(e) => {
+                let caller = std::panic::Location::caller();
+                tracing::error!(error = %e, at = %format_args!("{}:{}", caller.file(), caller.line()), "{}", context);
+                None
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Request Tracing
+// =============================================================================
+
+/// Runs each request inside a span carrying a request ID, the method and the
+/// matched route, and logs the status and latency when it finishes. The ID is
+/// taken from `X-Request-Id` when a proxy sent a sensible one and is echoed
+/// back in the response.
+pub async fn trace_requests(request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get("x-request-id")
+        .and_then(|v| v.to_str().ok())
+        .filter(|v| valid_request_id(v))
+        .map(str::to_string)
+        .unwrap_or_else(|| nanoid::nanoid!(12));
+    // The route pattern rather than the path, so paste IDs stay out of logs
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "-".to_string());
+
+    let span = tracing::info_span!("request", id = %id, method = %request.method(), route = %route);
+    let started = Instant::now();
+    let mut response = next.run(request).instrument(span.clone()).await;
+
+    let status = response.status().as_u16();
+    // Microseconds are plenty
+    let latency_ms = (started.elapsed().as_secs_f64() * 1_000_000.0).round() / 1000.0;
+    span.in_scope(|| {
+        if response.status().is_server_error() {
+            tracing::warn!(status, latency_ms, "Request failed");
+        } else {
+            tracing::info!(status, latency_ms, "Request finished");
+        }
+    });
+
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert("x-request-id", value);
+    }
+    response
+}
+
+fn valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.bytes().all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+}
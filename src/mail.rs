@@ -0,0 +1,95 @@
+use lettre::{
+    message::{header::ContentType, Mailbox},
+    transport::smtp::authentication::Credentials,
+    Address, AsyncSmtpTransport, AsyncTransport, Message, Tokio1Executor,
+};
+
+use crate::config::{MailConfig, SmtpSecurity};
+
+/// Sends account email through the configured SMTP relay
+pub struct Mailer {
+    transport: AsyncSmtpTransport<Tokio1Executor>,
+    from: Mailbox,
+    public_url: String,
+}
+
+impl Mailer {
+    /// `None` when no SMTP host is configured
+    pub fn from_config(config: &MailConfig, public_url: &str) -> Result<Option<Self>, String> {
+        let Some(host) = &config.smtp_host else {
+            return Ok(None);
+        };
+
+        let mut builder = match config.smtp_security {
+            SmtpSecurity::None => AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(host),
+            SmtpSecurity::StartTls => AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(host)
+                .map_err(|e| format!("SMTP_HOST is invalid: {}", e))?,
+            SmtpSecurity::Tls => AsyncSmtpTransport::<Tokio1Executor>::relay(host)
+                .map_err(|e| format!("SMTP_HOST is invalid: {}", e))?,
+        };
+        if let Some(port) = config.smtp_port {
+            builder = builder.port(port);
+        }
+        if let (Some(user), Some(pass)) = (&config.smtp_username, &config.smtp_password) {
+            builder = builder.credentials(Credentials::new(user.clone(), pass.clone()));
+        }
+
+        let from = config
+            .from
+            .parse()
+            .map_err(|e| format!("MAIL_FROM is not a valid address: {}", e))?;
+
+        Ok(Some(Self {
+            transport: builder.build(),
+            from,
+            public_url: public_url.to_string(),
+        }))
+    }
+
+    async fn send(&self, to: &str, subject: &str, body: String) -> Result<(), String> {
+        let to: Mailbox = to.parse().map_err(|e| format!("invalid recipient: {}", e))?;
+        let message = Message::builder()
+            .from(self.from.clone())
+            .to(to)
+            .subject(subject)
+            .header(ContentType::TEXT_PLAIN)
+            .body(body)
+            .map_err(|e| e.to_string())?;
+
+        self.transport
+            .send(message)
+            .await
+            .map(|_| ())
+            .map_err(|e| e.to_string())
+    }
+
+    pub async fn send_verification(&self, to: &str, username: &str, token: &str) -> Result<(), String> {
+        let body = format!(
+            "Hi {},\n\n\
+             Confirm this address for your OxidePaste account by opening the link below:\n\n\
+             {}/verify-email?token={}\n\n\
+             If you didn't ask for this, you can ignore this email.\n",
+            username, self.public_url, token
+        );
+        self.send(to, "Confirm your email address", body).await
+    }
+
+    pub async fn send_password_reset(&self, to: &str, username: &str, token: &str) -> Result<(), String> {
+        let body = format!(
+            "Hi {},\n\n\
+             Someone asked to reset the password for your OxidePaste account.\n\
+             Open the link below to choose a new one. It can only be used once.\n\n\
+             {}/reset-password?token={}\n\n\
+             If you didn't ask for this, you can ignore this email.\n\
+             Your password stays the same until the link is used.\n",
+            username, self.public_url, token
+        );
+        self.send(to, "Reset your password", body).await
+    }
+}
+
+/// Normalised form of a user-entered email address, or `None` if invalid
+pub fn normalize_address(input: &str) -> Option<String> {
+    let address: Address = input.trim().parse().ok()?;
+    Some(address.to_string().to_lowercase())
+}
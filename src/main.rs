@@ -2,53 +2,336 @@ mod models;
 mod handlers;
 mod db;
 mod utils;
+mod config;
+mod state;
+mod ratelimit;
+mod twofactor;
+mod mail;
+mod oidc;
+mod secrets;
+mod spam;
+mod security;
+mod assets;
+mod tls;
+mod server;
+mod tasks;
+mod logging;
+mod metrics;
+mod health;
+mod healthcheck;
+mod error;
+mod cache;
 
-use axum::{routing::get, Router};
+use axum::{
+    extract::DefaultBodyLimit,
+    middleware,
+    routing::{get, post},
+    Router,
+};
 use dotenvy::dotenv;
-use std::net::SocketAddr;
-use tokio::time::{interval, Duration};
+use std::sync::Arc;
+use std::time::Instant;
+use tokio::task::JoinSet;
+use tokio::time::interval;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tracing::{error, info, warn};
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     dotenv().ok();
+    let config = config::Config::from_env()?;
+    if std::env::args().nth(1).as_deref() == Some("--healthcheck") {
+        return Ok(healthcheck::run(&config).await?);
+    }
+    logging::init(&config.logging)?;
 
-    println!("OxidePaste: Initializing database...");
+    info!("Initializing database");
     let pool = db::init_db().await;
-    println!("OxidePaste: Database ready");
+    info!("Database ready");
+
+    match db::grant_admins(&pool, &config.auth.admin_usernames).await {
+        Ok(0) => {}
+        Ok(n) => info!(accounts = n, "Granted admin from ADMIN_USERNAMES"),
+        Err(e) => error!(error = %e, "Failed to apply ADMIN_USERNAMES"),
+    }
+
+    // Made now so the first sign-in with an unknown username isn't slower
+    utils::dummy_hash(&config.passwords);
+
+    let limiter = Arc::new(ratelimit::RateLimiter::new(config.rate_limits.clone()));
+    let mailer = mail::Mailer::from_config(&config.mail, &config.public_url)?.map(Arc::new);
+    if mailer.is_none() {
+        info!("SMTP_HOST not set, email and password reset are disabled");
+    }
+
+    let spam = Arc::new(spam::SpamFilter::from_config(&config.spam)?);
+    let metrics = Arc::new(metrics::Metrics::new());
+    let pastes = Arc::new(cache::PasteCache::new(config.cache.clone()));
+
+    let shutdown = CancellationToken::new();
+    let supervisor = tasks::Supervisor::new(shutdown.clone());
 
     // Background cleanup task
     let cleanup_pool = pool.clone();
-    tokio::spawn(async move {
-        let mut interval = interval(Duration::from_secs(300));
-        loop {
-            interval.tick().await;
-            if let Ok(count) = db::cleanup_expired_pastes(&cleanup_pool).await {
-                if count > 0 {
-                    println!("OxidePaste: Cleaned up {} expired pastes", count);
+    let cleanup_limiter = limiter.clone();
+    let cleanup_spam = spam.clone();
+    let cleanup_metrics = metrics.clone();
+    let cleanup_pastes = pastes.clone();
+    let lockout_window = config.lockout.window;
+    let cleanup = Arc::new(tasks::Heartbeat::new());
+    let cleanup_heartbeat = cleanup.clone();
+    supervisor.spawn("cleanup", move |shutdown| {
+        let pool = cleanup_pool.clone();
+        let limiter = cleanup_limiter.clone();
+        let spam = cleanup_spam.clone();
+        let metrics = cleanup_metrics.clone();
+        let pastes = cleanup_pastes.clone();
+        let heartbeat = cleanup_heartbeat.clone();
+        async move {
+            let mut interval = interval(tasks::CLEANUP_INTERVAL);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.cancelled() => return,
+                }
+                match db::cleanup_expired_pastes(&pool).await {
+                    Ok(0) => {}
+                    Ok(count) => {
+                        pastes.clear();
+                        metrics.expired_deleted(count);
+                        info!(count, "Cleaned up expired pastes");
+                    }
+                    Err(e) => error!(error = %e, "Failed to clean up expired pastes"),
                 }
+                let now = chrono::Utc::now().naive_utc();
+                let age = |d| chrono::Duration::from_std(d).unwrap_or_else(|_| chrono::Duration::days(90));
+                let pruned = db::prune_login_attempts(
+                    &pool,
+                    now - age(lockout_window),
+                    now - age(tasks::LOGIN_HISTORY.max(lockout_window)),
+                )
+                .await;
+                match pruned {
+                    Ok(0) => {}
+                    Ok(count) => info!(count, "Pruned old sign-in attempts"),
+                    Err(e) => error!(error = %e, "Failed to prune sign-in attempts"),
+                }
+                limiter.prune();
+                spam.prune();
+                heartbeat.beat();
             }
         }
     });
 
+    // View counts are collected in memory and written in batches
+    let flush_pool = pool.clone();
+    let flush_pastes = pastes.clone();
+    let flush_interval = config.cache.view_flush_interval;
+    supervisor.spawn("view counts", move |shutdown| {
+        let pool = flush_pool.clone();
+        let pastes = flush_pastes.clone();
+        async move {
+            let mut interval = interval(flush_interval);
+            loop {
+                tokio::select! {
+                    _ = interval.tick() => {}
+                    _ = shutdown.cancelled() => return,
+                }
+                if let Err(e) = pastes.flush(&pool).await {
+                    error!(error = %e, "Failed to write view counts");
+                }
+            }
+        }
+    });
+
+    let sso = oidc::Provider::from_config(&config.oidc)?.map(Arc::new);
+
+    let secrets = secrets::SecretScanner::from_config(&config.secrets)?.map(Arc::new);
+    match &secrets {
+        Some(scanner) => info!(patterns = scanner.pattern_count(), "Scanning new pastes for secrets"),
+        None => info!("SECRET_POLICY is off, pastes are not scanned for secrets"),
+    }
+
+    let tls = match &config.tls {
+        Some(tls_config) => {
+            let (acceptor, watcher) = tls::acceptor(tls_config)?;
+            supervisor.spawn("certificate reload", move |shutdown| watcher.clone().run(shutdown));
+            Some(acceptor)
+        }
+        None => None,
+    };
+    let redirect = config.tls.as_ref().and_then(|t| t.redirect_addr);
+    let public_url = config.public_url.clone();
+    let listen = config.listen.clone();
+    let metrics_addr = config.metrics.addr;
+
+    let state = state::AppState {
+        pool: pool.clone(),
+        config: Arc::new(config),
+        limiter,
+        mailer,
+        sso,
+        secrets,
+        spam,
+        metrics,
+        pastes,
+        cleanup,
+    };
+
     let app = Router::new()
         // Main routes
         .route("/", get(handlers::index).post(handlers::create_paste))
         .route("/:id", get(handlers::view_paste).post(handlers::verify_paste_password))
         .route("/:id/raw", get(handlers::view_raw))
         .route("/:id/delete", get(handlers::delete_paste))
+        .route("/:id/report", get(handlers::report_page).post(handlers::report_paste))
         // Auth routes
         .route("/login", get(handlers::login_page).post(handlers::login))
+        .route("/login/oidc", get(handlers::sso_login))
+        .route("/login/oidc/callback", get(handlers::sso_callback))
+        .route("/login/2fa", get(handlers::login_two_factor_page).post(handlers::login_two_factor))
+        .route("/forgot-password", get(handlers::forgot_password_page).post(handlers::forgot_password))
+        .route("/reset-password", get(handlers::reset_password_page).post(handlers::reset_password))
+        .route("/verify-email", get(handlers::verify_email))
         .route("/register", get(handlers::register_page).post(handlers::register))
         .route("/logout", get(handlers::logout))
         .route("/dashboard", get(handlers::dashboard))
+        .route("/2fa", get(handlers::two_factor_page))
+        .route("/2fa/enable", post(handlers::enable_two_factor))
+        .route("/2fa/restart", post(handlers::restart_two_factor))
+        .route("/2fa/recovery-codes", post(handlers::regenerate_recovery_codes))
+        .route("/2fa/disable", post(handlers::disable_two_factor))
+        .route("/settings", get(handlers::settings_page))
+        .route("/settings/password", post(handlers::change_password))
+        .route("/settings/sessions/revoke", post(handlers::revoke_session))
+        .route("/settings/sessions/revoke-others", post(handlers::revoke_other_sessions))
+        .route("/settings/email", post(handlers::change_email))
+        .route("/settings/email/remove", post(handlers::remove_email))
+        .route("/settings/tokens", post(handlers::create_api_token))
+        .route("/settings/tokens/:id/delete", post(handlers::delete_api_token))
+        .route("/settings/invites", post(handlers::create_invite))
+        .route("/settings/invites/:id/delete", post(handlers::delete_invite))
+        .route("/settings/delete", post(handlers::delete_account))
         .route("/public", get(handlers::public_pastes))
-        .with_state(pool);
+        .route("/challenge", get(handlers::spam_challenge))
+        .route("/static/*path", get(handlers::static_file))
+        .route("/healthz", get(health::healthz))
+        .route("/readyz", get(health::readyz))
+        // Admin routes
+        .route("/admin", get(handlers::admin_page))
+        .route("/admin/pastes", get(handlers::admin_pastes))
+        .route("/admin/pastes/:id/:action", post(handlers::admin_paste_action))
+        .route("/admin/reports", get(handlers::admin_reports))
+        .route("/admin/reports/:id/:action", post(handlers::admin_report_action))
+        .route("/admin/users", get(handlers::admin_users))
+        .route("/admin/users/:id/:action", post(handlers::admin_user_action));
+    let app = if state.config.metrics.on_main_listeners() {
+        app.route("/metrics", get(metrics::endpoint))
+    } else {
+        app
+    };
+    let app = app
+        .layer(middleware::from_fn_with_state(state.clone(), handlers::require_two_factor))
+        .layer(DefaultBodyLimit::max(state.config.limits.max_body_bytes))
+        .layer(middleware::from_fn(error::error_pages))
+        .layer(middleware::from_fn_with_state(state.clone(), security::security_headers))
+        .layer(middleware::from_fn_with_state(state.clone(), metrics::track_requests))
+        .layer(middleware::from_fn(logging::trace_requests))
+        .with_state(state.clone());
+
+    let listeners = server::bind(&listen).await?;
+    let connections = TaskTracker::new();
+    let mut servers = JoinSet::new();
+
+    if let Some(redirect_addr) = redirect {
+        let redirect_listener = tokio::net::TcpListener::bind(redirect_addr).await?;
+        info!(%redirect_addr, %public_url, "Redirecting plain HTTP to PUBLIC_URL");
+        let shutdown = shutdown.clone();
+        servers.spawn(async move {
+            if let Err(e) = tls::redirect(redirect_listener, public_url, shutdown).await {
+                error!(error = %e, "HTTP redirect listener stopped");
+            }
+        });
+    }
+
+    if let Some(metrics_addr) = metrics_addr {
+        let metrics_listener = tokio::net::TcpListener::bind(metrics_addr).await?;
+        info!(%metrics_addr, "Serving metrics");
+        let shutdown = shutdown.clone();
+        let state = state.clone();
+        servers.spawn(async move {
+            if let Err(e) = metrics::serve(metrics_listener, state, shutdown).await {
+                error!(error = %e, "Metrics listener stopped");
+            }
+        });
+    }
+
+    for listener in listeners {
+        let scheme = match (&listener, &tls) {
+            (server::Listener::Tcp(_), Some(_)) => "https://",
+            (server::Listener::Tcp(_), None) => "http://",
+            #[cfg(unix)]
+            (server::Listener::Unix(..), _) => "",
+        };
+        info!(address = %format_args!("{}{}", scheme, listener.describe()), "Server running");
+        #[cfg(unix)]
+        if matches!(listener, server::Listener::Unix(..)) && state.config.rate_limits.trusted_proxy_hops == 0 {
+            warn!(
+                address = %listener.describe(),
+                "Unix socket connections have no client address, so per-IP rate limits don't apply to them; \
+                 set TRUSTED_PROXY_HOPS to use X-Forwarded-For"
+            );
+        }
+        servers.spawn(server::serve(
+            listener,
+            tls.clone(),
+            app.clone(),
+            connections.clone(),
+            shutdown.clone(),
+        ));
+    }
+
+    tasks::shutdown_signal().await;
+
+    // Stop accepting, let requests in flight finish, then stop the tasks
+    let started = Instant::now();
+    let deadline = started + listen.shutdown_timeout;
+    info!(
+        open_connections = connections.len(),
+        timeout_secs = listen.shutdown_timeout.as_secs(),
+        "Shutting down"
+    );
+    shutdown.cancel();
+    connections.close();
+
+    let drained = tokio::time::timeout_at(deadline.into(), async {
+        while servers.join_next().await.is_some() {}
+        connections.wait().await;
+    })
+    .await
+    .is_ok();
+    let tasks_stopped = supervisor.stop(deadline.saturating_duration_since(Instant::now())).await;
+
+    // Views counted since the last flush, including by the final requests
+    if let Err(e) = state.pastes.flush(&pool).await {
+        error!(error = %e, "Failed to write view counts");
+    }
+
+    // Requests still running hold database connections, which close() would
+    // wait for
+    if drained {
+        pool.close().await;
+    }
 
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
-    println!("OxidePaste: Server running at http://0.0.0.0:3000");
-    
-    let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    let cut_off = if drained { 0 } else { connections.len() };
+    info!(
+        elapsed_secs = started.elapsed().as_secs_f64(),
+        cut_off_connections = cut_off,
+        tasks_stopped,
+        task_restarts = supervisor.restarts(),
+        "Stopped"
+    );
 
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,285 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header, HeaderMap, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::get,
+    Router,
+};
+use sqlx::SqlitePool;
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+use crate::db;
+use crate::logging::LogErr;
+use crate::ratelimit::bearer_token;
+use crate::state::AppState;
+use crate::utils::hash_token;
+
+/// Upper bounds of the request latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 11] = [0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+
+/// Expiration choices offered on the paste form. Anything else is counted as
+/// `custom` so API clients can't blow up the number of series.
+const EXPIRY_PRESETS: [&str; 6] = ["never", "10m", "1h", "24h", "7d", "30d"];
+
+// =============================================================================
+// Recording
+// =============================================================================
+
+#[derive(Debug, Clone, Copy)]
+pub enum LoginFailure {
+    /// Unknown user or wrong password
+    Password,
+    /// Wrong authenticator or recovery code
+    TwoFactor,
+    Locked,
+    Disabled,
+}
+
+impl LoginFailure {
+    const ALL: [Self; 4] = [Self::Password, Self::TwoFactor, Self::Locked, Self::Disabled];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Password => "password",
+            Self::TwoFactor => "two_factor",
+            Self::Locked => "locked",
+            Self::Disabled => "disabled",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; LATENCY_BUCKETS.len()],
+    sum: f64,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, seconds: f64) {
+        for (bucket, le) in self.buckets.iter_mut().zip(LATENCY_BUCKETS) {
+            if seconds <= le {
+                *bucket += 1;
+            }
+        }
+        self.sum += seconds;
+        self.count += 1;
+    }
+}
+
+#[derive(Default)]
+struct RouteStats {
+    statuses: BTreeMap<u16, u64>,
+    latency: Histogram,
+}
+
+/// Counters for `/metrics`. Gauges such as pool usage and storage are read
+/// when scraped instead.
+#[derive(Default)]
+pub struct Metrics {
+    /// Keyed by method and route pattern
+    requests: Mutex<BTreeMap<(String, String), RouteStats>>,
+    /// Keyed by language and expiry
+    pastes_created: Mutex<BTreeMap<(String, &'static str), u64>>,
+    paste_views: AtomicU64,
+    unlock_failures: AtomicU64,
+    login_failures: [AtomicU64; LoginFailure::ALL.len()],
+    expired_deleted: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn request(&self, method: &str, route: &str, status: u16, latency: Duration) {
+        let mut requests = self.requests.lock().unwrap();
+        let stats = requests.entry((method.to_string(), route.to_string())).or_default();
+        *stats.statuses.entry(status).or_default() += 1;
+        stats.latency.observe(latency.as_secs_f64());
+    }
+
+    pub fn paste_created(&self, language: &str, expiry: &'static str) {
+        *self.pastes_created.lock().unwrap().entry((language.to_string(), expiry)).or_default() += 1;
+    }
+
+    pub fn paste_viewed(&self) {
+        self.paste_views.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn unlock_failed(&self) {
+        self.unlock_failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn login_failed(&self, reason: LoginFailure) {
+        self.login_failures[reason as usize].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn expired_deleted(&self, count: u64) {
+        self.expired_deleted.fetch_add(count, Ordering::Relaxed);
+    }
+}
+
+/// Label for a paste's expiration as entered on the form or API
+pub fn expiry_label(expiration: Option<&str>) -> &'static str {
+    let value = expiration.filter(|e| !e.is_empty()).unwrap_or("never");
+    EXPIRY_PRESETS.iter().find(|p| **p == value).copied().unwrap_or("custom")
+}
+
+/// Count every request and its latency under its route pattern
+pub async fn track_requests(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let method = request.method().to_string();
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|p| p.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let started = Instant::now();
+    let response = next.run(request).await;
+    state.metrics.request(&method, &route, response.status().as_u16(), started.elapsed());
+    response
+}
+
+// =============================================================================
+// Exposition
+// =============================================================================
+
+/// `/metrics` in the Prometheus text format. When `METRICS_TOKEN` is set the
+/// scraper has to send it as a bearer token.
+pub async fn endpoint(State(state): State<AppState>, headers: HeaderMap) -> Response {
+    if let Some(token) = &state.config.metrics.token {
+        // Compare digests so the time taken says nothing about the token
+        if bearer_token(&headers).map(hash_token) != Some(hash_token(token)) {
+            return (
+                StatusCode::UNAUTHORIZED,
+                [(header::WWW_AUTHENTICATE, "Bearer")],
+                "Missing or invalid metrics token",
+            )
+                .into_response();
+        }
+    }
+
+    // Left out rather than reported as zero when the database can't be read
+    let storage = db::storage_bytes(&state.pool).await.log_err("Failed to read storage size");
+    let body = render(&state.metrics, &state.pool, storage);
+    ([(header::CONTENT_TYPE, "text/plain; version=0.0.4; charset=utf-8")], body).into_response()
+}
+
+/// Plain HTTP listener on `METRICS_ADDR` that serves only `/metrics`
+pub async fn serve(listener: TcpListener, state: AppState, shutdown: CancellationToken) -> io::Result<()> {
+    let app = Router::new().route("/metrics", get(endpoint)).with_state(state);
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+}
+
+fn render(metrics: &Metrics, pool: &SqlitePool, storage: Option<(i64, i64)>) -> String {
+    let mut out = String::new();
+
+    family(&mut out, "oxidepaste_http_requests_total", "counter", "Requests handled, by route and status");
+    let requests = metrics.requests.lock().unwrap();
+    for ((method, route), stats) in requests.iter() {
+        for (status, count) in &stats.statuses {
+            let labels = labels(&[("method", method), ("route", route), ("status", &status.to_string())]);
+            sample(&mut out, "oxidepaste_http_requests_total", &labels, *count);
+        }
+    }
+
+    family(
+        &mut out,
+        "oxidepaste_http_request_duration_seconds",
+        "histogram",
+        "Time taken to answer requests, by route",
+    );
+    for ((method, route), stats) in requests.iter() {
+        let latency = &stats.latency;
+        for (le, count) in LATENCY_BUCKETS.iter().zip(latency.buckets) {
+            let labels = labels(&[("method", method), ("route", route), ("le", &le.to_string())]);
+            sample(&mut out, "oxidepaste_http_request_duration_seconds_bucket", &labels, count);
+        }
+        let labels_inf = labels(&[("method", method), ("route", route), ("le", "+Inf")]);
+        sample(&mut out, "oxidepaste_http_request_duration_seconds_bucket", &labels_inf, latency.count);
+        let labels = labels(&[("method", method), ("route", route)]);
+        sample(&mut out, "oxidepaste_http_request_duration_seconds_sum", &labels, latency.sum);
+        sample(&mut out, "oxidepaste_http_request_duration_seconds_count", &labels, latency.count);
+    }
+    drop(requests);
+
+    family(&mut out, "oxidepaste_pastes_created_total", "counter", "Pastes created, by language and expiry");
+    for ((language, expiry), count) in metrics.pastes_created.lock().unwrap().iter() {
+        let labels = labels(&[("language", language), ("expiry", expiry)]);
+        sample(&mut out, "oxidepaste_pastes_created_total", &labels, *count);
+    }
+
+    family(&mut out, "oxidepaste_paste_views_total", "counter", "Paste pages served");
+    sample(&mut out, "oxidepaste_paste_views_total", "", metrics.paste_views.load(Ordering::Relaxed));
+
+    family(
+        &mut out,
+        "oxidepaste_paste_unlock_failures_total",
+        "counter",
+        "Wrong passwords entered for protected pastes",
+    );
+    sample(&mut out, "oxidepaste_paste_unlock_failures_total", "", metrics.unlock_failures.load(Ordering::Relaxed));
+
+    family(&mut out, "oxidepaste_login_failures_total", "counter", "Sign-ins refused, by reason");
+    for reason in LoginFailure::ALL {
+        let count = metrics.login_failures[reason as usize].load(Ordering::Relaxed);
+        sample(&mut out, "oxidepaste_login_failures_total", &labels(&[("reason", reason.label())]), count);
+    }
+
+    family(
+        &mut out,
+        "oxidepaste_expired_pastes_deleted_total",
+        "counter",
+        "Expired pastes removed by the cleanup task",
+    );
+    sample(&mut out, "oxidepaste_expired_pastes_deleted_total", "", metrics.expired_deleted.load(Ordering::Relaxed));
+
+    let open = pool.size() as usize;
+    let idle = pool.num_idle().min(open);
+    family(&mut out, "oxidepaste_db_pool_connections", "gauge", "Open database connections, by state");
+    sample(&mut out, "oxidepaste_db_pool_connections", &labels(&[("state", "in_use")]), open - idle);
+    sample(&mut out, "oxidepaste_db_pool_connections", &labels(&[("state", "idle")]), idle);
+    family(&mut out, "oxidepaste_db_pool_max_connections", "gauge", "Most connections the pool will open");
+    sample(&mut out, "oxidepaste_db_pool_max_connections", "", pool.options().get_max_connections());
+
+    if let Some((content, database)) = storage {
+        family(&mut out, "oxidepaste_stored_content_bytes", "gauge", "Size of all paste content after deduplication");
+        sample(&mut out, "oxidepaste_stored_content_bytes", "", content);
+        family(&mut out, "oxidepaste_database_bytes", "gauge", "Size of the SQLite database file");
+        sample(&mut out, "oxidepaste_database_bytes", "", database);
+    }
+
+    out
+}
+
+fn family(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl std::fmt::Display) {
+    let _ = writeln!(out, "{}{} {}", name, labels, value);
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let pairs: Vec<String> = pairs
+        .iter()
+        .map(|(name, value)| {
+            let value = value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n");
+            format!("{}=\"{}\"", name, value)
+        })
+        .collect();
+    format!("{{{}}}", pairs.join(","))
+}
@@ -1,6 +1,7 @@
 use serde::{Deserialize, Serialize};
 use sqlx::FromRow;
 use chrono::NaiveDateTime;
+use std::str::FromStr;
 
 // =============================================================================
 // User Models
@@ -12,6 +13,19 @@ pub struct User {
     pub username: String,
     pub password_hash: String,
     pub created_at: NaiveDateTime,
+    pub totp_enabled: bool,
+    /// Verified email address, used for password resets
+    pub email: Option<String>,
+    pub is_admin: bool,
+    /// Disabled accounts can't sign in or use their sessions and tokens
+    pub disabled: bool,
+}
+
+impl User {
+    /// Accounts created through single sign-on have no local password
+    pub fn has_password(&self) -> bool {
+        !self.password_hash.is_empty()
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -19,6 +33,16 @@ pub struct RegisterForm {
     pub username: String,
     pub password: String,
     pub confirm_password: String,
+    /// Required when registration is invite-only
+    #[serde(default)]
+    pub invite: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RegisterQuery {
+    /// Invite code from an invite link
+    #[serde(default)]
+    pub invite: String,
 }
 
 #[derive(Debug, Deserialize)]
@@ -27,6 +51,146 @@ pub struct LoginForm {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct TwoFactorForm {
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DisableTwoFactorForm {
+    #[serde(default)]
+    pub password: String,
+    pub code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ChangePasswordForm {
+    pub current_password: String,
+    pub new_password: String,
+    pub confirm_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct EmailForm {
+    pub email: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ForgotPasswordForm {
+    /// Username or email address
+    pub account: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ResetPasswordForm {
+    pub token: String,
+    pub password: String,
+    pub confirm_password: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct OidcCallbackQuery {
+    #[serde(default)]
+    pub code: Option<String>,
+    #[serde(default)]
+    pub state: Option<String>,
+    /// Set by the provider when the user cancels or the request is refused
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenQuery {
+    #[serde(default)]
+    pub token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RevokeSessionForm {
+    pub session: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateTokenForm {
+    pub name: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CreateInviteForm {
+    #[serde(default = "default_invite_uses")]
+    pub uses: i64,
+    /// Same values as paste expiration, e.g. `7d` or `never`
+    #[serde(default)]
+    pub expiration: String,
+}
+
+fn default_invite_uses() -> i64 {
+    1
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountForm {
+    #[serde(default)]
+    pub password: String,
+    /// Stands in for the password on accounts that don't have one
+    #[serde(default)]
+    pub confirm_username: String,
+    /// Required when two-factor authentication is on
+    #[serde(default)]
+    pub code: Option<String>,
+    /// `delete` removes the user's pastes, `orphan` keeps them without an owner
+    pub pastes: String,
+}
+
+/// A signed-in browser, as listed on the settings page
+#[derive(Debug, FromRow)]
+pub struct Session {
+    pub token_hash: String,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A personal API token; only its hash is stored
+#[derive(Debug, FromRow)]
+pub struct ApiToken {
+    pub id: i64,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+    pub last_used_at: Option<NaiveDateTime>,
+}
+
+/// An invite code for invite-only registration
+#[derive(Debug, FromRow)]
+pub struct Invite {
+    pub id: i64,
+    pub code: String,
+    pub max_uses: i64,
+    pub uses: i64,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+impl Invite {
+    /// Whether the code can still be used to register
+    pub fn is_active(&self) -> bool {
+        let unexpired = match self.expires_at {
+            Some(expires_at) => expires_at > chrono::Utc::now().naive_utc(),
+            None => true,
+        };
+        self.uses < self.max_uses && unexpired
+    }
+}
+
+/// One row of a user's sign-in history
+#[derive(Debug, FromRow)]
+pub struct LoginAttempt {
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+    pub success: bool,
+    pub created_at: NaiveDateTime,
+}
+
 // =============================================================================
 // Paste Models
 // =============================================================================
@@ -41,9 +205,35 @@ pub struct Paste {
     pub created_at: NaiveDateTime,
     pub view_count: i32,
     pub user_id: Option<i64>,
+    /// Set while the paste is held back by moderation
+    pub hidden: Option<HiddenState>,
+    /// Only the owner can open it
+    pub private: bool,
 }
 
-#[derive(Debug, Deserialize)]
+/// Why a paste is hidden, stored in `pastes.hidden`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, sqlx::Type)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum HiddenState {
+    /// Reported often enough to drop off the public list until reviewed
+    Review,
+    /// Taken down by an admin; only its owner and admins can open it
+    Moderator,
+}
+
+/// Fields needed to insert a paste; the body is stored once per content hash.
+pub struct NewPaste<'a> {
+    pub id: &'a str,
+    pub content: &'a str,
+    pub language: &'a str,
+    pub password_hash: Option<&'a str>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub user_id: Option<i64>,
+    pub private: bool,
+}
+
+#[derive(Debug, Default, Deserialize)]
 pub struct CreatePasteForm {
     pub content: String,
     #[serde(default)]
@@ -52,6 +242,21 @@ pub struct CreatePasteForm {
     pub password: Option<String>,
     #[serde(default)]
     pub expiration: Option<String>,
+    /// Vanity ID chosen by a logged-in user
+    #[serde(default)]
+    pub slug: Option<String>,
+    /// Owner-only paste, for logged-in users
+    #[serde(default)]
+    pub private: bool,
+    /// Answer to a possible-secrets warning: `private` stores the paste as
+    /// private, `ignore` stores it as submitted
+    #[serde(default)]
+    pub secrets: Option<String>,
+    /// Proof-of-work challenge and its solution, from anonymous visitors
+    #[serde(default)]
+    pub pow_challenge: Option<String>,
+    #[serde(default)]
+    pub pow_nonce: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -59,6 +264,164 @@ pub struct PasswordForm {
     pub password: String,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct ReportForm {
+    pub reason: String,
+    #[serde(default)]
+    pub details: String,
+}
+
+// =============================================================================
+// Reports
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportReason {
+    Spam,
+    Malware,
+    Credentials,
+    Illegal,
+    Other,
+}
+
+impl ReportReason {
+    pub const ALL: [Self; 5] = [Self::Spam, Self::Malware, Self::Credentials, Self::Illegal, Self::Other];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Spam => "spam",
+            Self::Malware => "malware",
+            Self::Credentials => "credentials",
+            Self::Illegal => "illegal",
+            Self::Other => "other",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Spam => "Spam or advertising",
+            Self::Malware => "Malware or phishing",
+            Self::Credentials => "Leaked passwords, keys or personal data",
+            Self::Illegal => "Illegal content",
+            Self::Other => "Something else",
+        }
+    }
+}
+
+impl FromStr for ReportReason {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|r| r.as_str() == s)
+            .ok_or("Choose a reason for the report")
+    }
+}
+
+/// An open report as shown in the moderation queue
+#[derive(Debug, FromRow)]
+pub struct Report {
+    pub paste_id: String,
+    pub reason: String,
+    pub details: Option<String>,
+    /// Username of the reporter, if they were signed in
+    pub reporter: Option<String>,
+    pub reporter_ip: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// A paste waiting in the moderation queue, with its open reports
+#[derive(Debug, FromRow)]
+pub struct ReportedPaste {
+    pub id: String,
+    pub owner: Option<String>,
+    pub hidden: Option<HiddenState>,
+    pub protected: bool,
+    pub report_count: i64,
+    pub last_reported_at: NaiveDateTime,
+    #[sqlx(skip)]
+    pub reports: Vec<Report>,
+}
+
+// =============================================================================
+// Admin Models
+// =============================================================================
+
+#[derive(Debug, Deserialize)]
+pub struct AdminSearchQuery {
+    #[serde(default)]
+    pub q: String,
+}
+
+/// Posted by the buttons in the admin lists
+#[derive(Debug, Deserialize)]
+pub struct AdminActionForm {
+    /// Search to show again once the action is done
+    #[serde(default)]
+    pub q: String,
+    /// For user deletion: `delete` or `orphan`, as in account deletion
+    #[serde(default)]
+    pub pastes: Option<String>,
+}
+
+/// A paste as listed in the admin area, without its body
+#[derive(Debug, FromRow)]
+pub struct AdminPaste {
+    pub id: String,
+    pub language: Option<String>,
+    pub owner: Option<String>,
+    pub size: i64,
+    pub protected: bool,
+    pub private: bool,
+    pub hidden: Option<HiddenState>,
+    pub expires_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+    pub view_count: i32,
+}
+
+/// An account as listed in the admin area
+#[derive(Debug, FromRow)]
+pub struct AdminUser {
+    pub id: i64,
+    pub username: String,
+    pub email: Option<String>,
+    pub created_at: NaiveDateTime,
+    pub totp_enabled: bool,
+    pub is_admin: bool,
+    pub disabled: bool,
+    pub paste_count: i64,
+}
+
+#[derive(Debug, FromRow)]
+pub struct AuditEntry {
+    pub admin_username: String,
+    pub action: String,
+    pub target: String,
+    pub details: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: NaiveDateTime,
+}
+
+/// Instance-wide numbers for the admin overview
+#[derive(Debug, Default)]
+pub struct InstanceStats {
+    pub pastes: i64,
+    pub protected_pastes: i64,
+    pub expiring_pastes: i64,
+    pub users: i64,
+    pub disabled_users: i64,
+    /// Pastes with reports nobody has dealt with yet
+    pub reported_pastes: i64,
+    /// Bytes of paste bodies as stored, after deduplication
+    pub content_bytes: i64,
+    /// Bytes of paste bodies counting every paste separately
+    pub logical_bytes: i64,
+    pub database_bytes: i64,
+    /// Most used languages with their paste counts
+    pub top_languages: Vec<(String, i64)>,
+}
+
 // =============================================================================
 // Expiration
 // =============================================================================
@@ -70,38 +433,63 @@ pub enum Expiration {
     Days(i64),
 }
 
-impl Expiration {
-    pub fn from_str(s: &str) -> Self {
+impl FromStr for Expiration {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
         if s.is_empty() || s == "never" {
-            return Self::Never;
+            return Ok(Self::Never);
         }
         
         let len = s.len();
-        if len < 2 {
-            return Self::Never;
+        if len < 2 || !s.is_char_boundary(len - 1) {
+            return Err("Invalid expiration");
         }
         
         let (num_str, unit) = s.split_at(len - 1);
-        let num: i64 = num_str.parse().unwrap_or(0);
+        if !num_str.bytes().all(|b| b.is_ascii_digit()) {
+            return Err("Invalid expiration");
+        }
+        let num: i64 = num_str.parse().map_err(|_| "Invalid expiration")?;
+        if num <= 0 {
+            return Err("Expiration must be greater than zero");
+        }
         
         match unit {
-            "m" => Self::Minutes(num),
-            "h" => Self::Hours(num),
-            "d" => Self::Days(num),
-            _ => Self::Never,
+            "m" => Ok(Self::Minutes(num)),
+            "h" => Ok(Self::Hours(num)),
+            "d" => Ok(Self::Days(num)),
+            _ => Err("Invalid expiration"),
         }
     }
-    
-    pub fn to_datetime(&self) -> Option<NaiveDateTime> {
+}
+
+impl Expiration {
+    /// Length of the expiration in minutes (`None` for never)
+    pub fn minutes(&self) -> Option<i64> {
+        match self {
+            Self::Never => None,
+            Self::Minutes(n) => Some(*n),
+            Self::Hours(n) => Some(n.saturating_mul(60)),
+            Self::Days(n) => Some(n.saturating_mul(24 * 60)),
+        }
+    }
+
+    /// When the expiration runs out, counted from now (`Ok(None)` for never).
+    /// Fails instead of overflowing when it is too far away to represent.
+    pub fn to_datetime(&self) -> Result<Option<NaiveDateTime>, &'static str> {
         use chrono::{Duration, Utc};
-        
+
         let duration = match self {
-            Self::Never => return None,
-            Self::Minutes(n) => Duration::minutes(*n),
-            Self::Hours(n) => Duration::hours(*n),
-            Self::Days(n) => Duration::days(*n),
+            Self::Never => return Ok(None),
+            Self::Minutes(n) => Duration::try_minutes(*n),
+            Self::Hours(n) => Duration::try_hours(*n),
+            Self::Days(n) => Duration::try_days(*n),
         };
-        
-        Some((Utc::now() + duration).naive_utc())
+
+        duration
+            .and_then(|d| Utc::now().naive_utc().checked_add_signed(d))
+            .map(Some)
+            .ok_or("Expiration is too far in the future")
     }
 }
\ No newline at end of file
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use openidconnect::core::{CoreAuthenticationFlow, CoreClient, CoreIdToken, CoreProviderMetadata};
+use openidconnect::{
+    reqwest, AuthorizationCode, ClaimsVerificationError, ClientId, ClientSecret, CsrfToken, EndpointMaybeSet,
+    EndpointNotSet, EndpointSet, IssuerUrl, Nonce, PkceCodeChallenge, PkceCodeVerifier, RedirectUrl, Scope,
+    TokenResponse,
+};
+use tokio::sync::RwLock;
+
+use crate::config::OidcConfig;
+
+type ProviderClient =
+    CoreClient<EndpointSet, EndpointNotSet, EndpointNotSet, EndpointNotSet, EndpointMaybeSet, EndpointMaybeSet>;
+
+/// Browser-side half of an authorization-code login with PKCE
+pub struct PendingLogin {
+    /// Where to send the browser
+    pub url: String,
+    pub state: String,
+    pub nonce: String,
+    pub pkce_verifier: String,
+}
+
+/// Who the provider says signed in
+pub struct Identity {
+    pub issuer: String,
+    pub subject: String,
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: bool,
+}
+
+/// An OpenID Connect provider, discovered on first use
+pub struct Provider {
+    config: OidcConfig,
+    http: reqwest::Client,
+    client: RwLock<Option<Arc<ProviderClient>>>,
+}
+
+impl Provider {
+    /// `None` when no issuer is configured
+    pub fn from_config(config: &OidcConfig) -> Result<Option<Self>, String> {
+        if config.issuer.is_none() {
+            return Ok(None);
+        }
+
+        // Following redirects from the provider would open us up to SSRF
+        let http = reqwest::ClientBuilder::new()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .map_err(|e| format!("Failed to build the OIDC HTTP client: {}", e))?;
+
+        Ok(Some(Self {
+            config: config.clone(),
+            http,
+            client: RwLock::new(None),
+        }))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.provider_name
+    }
+
+    /// Fetch the discovery document the first time it is needed, or again when
+    /// `refresh` is set. A failed attempt is retried on the next login rather than cached.
+    async fn client(&self, refresh: bool) -> Result<Arc<ProviderClient>, String> {
+        if !refresh {
+            if let Some(client) = self.client.read().await.as_ref() {
+                return Ok(client.clone());
+            }
+        }
+
+        let issuer = IssuerUrl::new(self.config.issuer.clone().unwrap_or_default())
+            .map_err(|e| format!("OIDC_ISSUER is invalid: {}", e))?;
+        let redirect = RedirectUrl::new(self.config.redirect_url.clone())
+            .map_err(|e| format!("OIDC_REDIRECT_URL is invalid: {}", e))?;
+        let metadata = CoreProviderMetadata::discover_async(issuer, &self.http)
+            .await
+            .map_err(|e| format!("OIDC discovery failed: {}", e))?;
+
+        let client = Arc::new(
+            CoreClient::from_provider_metadata(
+                metadata,
+                ClientId::new(self.config.client_id.clone()),
+                self.config.client_secret.clone().map(ClientSecret::new),
+            )
+            .set_redirect_uri(redirect),
+        );
+        *self.client.write().await = Some(client.clone());
+        Ok(client)
+    }
+
+    pub async fn begin(&self) -> Result<PendingLogin, String> {
+        let client = self.client(false).await?;
+        let (pkce_challenge, pkce_verifier) = PkceCodeChallenge::new_random_sha256();
+
+        let mut request = client
+            .authorize_url(CoreAuthenticationFlow::AuthorizationCode, CsrfToken::new_random, Nonce::new_random)
+            .set_pkce_challenge(pkce_challenge);
+        // `openid` is always requested
+        for scope in self.config.scopes.iter().filter(|s| s.as_str() != "openid") {
+            request = request.add_scope(Scope::new(scope.clone()));
+        }
+        let (url, state, nonce) = request.url();
+
+        Ok(PendingLogin {
+            url: url.to_string(),
+            state: state.secret().clone(),
+            nonce: nonce.secret().clone(),
+            pkce_verifier: pkce_verifier.secret().clone(),
+        })
+    }
+
+    /// Redeem the authorization code and check the ID token it comes with
+    pub async fn finish(&self, code: &str, pkce_verifier: &str, nonce: &str) -> Result<Identity, String> {
+        let client = self.client(false).await?;
+
+        let response = client
+            .exchange_code(AuthorizationCode::new(code.to_string()))
+            .map_err(|e| format!("Provider has no token endpoint: {}", e))?
+            .set_pkce_verifier(PkceCodeVerifier::new(pkce_verifier.to_string()))
+            .request_async(&self.http)
+            .await
+            .map_err(|e| format!("Token exchange failed: {}", e))?;
+        let id_token = response.id_token().ok_or("Provider did not return an ID token")?;
+        let nonce = Nonce::new(nonce.to_string());
+
+        match Self::identity(&client, id_token, &nonce) {
+            // The provider may have rotated its signing keys since discovery
+            Err(ClaimsVerificationError::SignatureVerification(_)) => {
+                let client = self.client(true).await?;
+                Self::identity(&client, id_token, &nonce)
+            }
+            result => result,
+        }
+        .map_err(|e| format!("ID token rejected: {}", e))
+    }
+
+    fn identity(
+        client: &ProviderClient,
+        id_token: &CoreIdToken,
+        nonce: &Nonce,
+    ) -> Result<Identity, ClaimsVerificationError> {
+        let claims = id_token.claims(&client.id_token_verifier(), nonce)?;
+
+        Ok(Identity {
+            issuer: claims.issuer().to_string(),
+            subject: claims.subject().to_string(),
+            preferred_username: claims.preferred_username().map(|u| u.to_string()),
+            email: claims.email().map(|e| e.to_string()),
+            email_verified: claims.email_verified().unwrap_or(false),
+        })
+    }
+}
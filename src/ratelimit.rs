@@ -0,0 +1,179 @@
+use axum::{
+    async_trait,
+    extract::{ConnectInfo, FromRequestParts},
+    http::{header, request::Parts, HeaderMap},
+};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::{RateLimit, RateLimitConfig};
+use crate::state::AppState;
+
+/// Prune stale windows eagerly once the table grows past this many keys
+const MAX_TRACKED_KEYS: usize = 100_000;
+
+// =============================================================================
+// Buckets
+// =============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Bucket {
+    CreatePaste,
+    Login,
+    Unlock,
+    Report,
+}
+
+struct Window {
+    started: Instant,
+    hits: u32,
+    length: Duration,
+}
+
+// =============================================================================
+// Limiter
+// =============================================================================
+
+/// Fixed-window request counter keyed by bucket and client/account key
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    windows: Mutex<HashMap<(Bucket, String), Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(config: RateLimitConfig) -> Self {
+        Self {
+            config,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn limit_for(&self, bucket: Bucket) -> Option<RateLimit> {
+        match bucket {
+            Bucket::CreatePaste => self.config.create_paste,
+            Bucket::Login => self.config.login,
+            Bucket::Unlock => self.config.unlock,
+            Bucket::Report => self.config.report,
+        }
+    }
+
+    /// Count a hit against the client's IP and each extra key (e.g. the
+    /// account or paste being targeted). Returns the number of seconds to
+    /// wait if any of them is over its limit.
+    pub fn check(&self, bucket: Bucket, client: &Client, keys: &[String]) -> Result<(), u64> {
+        let Some(limit) = self.limit_for(bucket) else {
+            return Ok(());
+        };
+        if client.exempt {
+            return Ok(());
+        }
+
+        let mut all_keys: Vec<String> = keys.to_vec();
+        if let Some(ip) = client.ip {
+            all_keys.push(format!("ip:{}", ip));
+        }
+
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+        if windows.len() > MAX_TRACKED_KEYS {
+            windows.retain(|_, w| now.duration_since(w.started) < w.length);
+        }
+
+        let mut retry_after = 0;
+        for key in all_keys {
+            let window = windows.entry((bucket, key)).or_insert(Window {
+                started: now,
+                hits: 0,
+                length: limit.window,
+            });
+            if now.duration_since(window.started) >= window.length {
+                window.started = now;
+                window.hits = 0;
+                window.length = limit.window;
+            }
+            window.hits = window.hits.saturating_add(1);
+            if window.hits > limit.max {
+                let remaining = window.length.saturating_sub(now.duration_since(window.started));
+                retry_after = retry_after.max(remaining.as_secs().max(1));
+            }
+        }
+
+        if retry_after > 0 {
+            Err(retry_after)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Drop windows that have already expired
+    pub fn prune(&self) {
+        let now = Instant::now();
+        self.windows
+            .lock()
+            .unwrap()
+            .retain(|_, w| now.duration_since(w.started) < w.length);
+    }
+}
+
+// =============================================================================
+// Client Extractor
+// =============================================================================
+
+/// Who is making the request, as far as rate limiting is concerned
+pub struct Client {
+    pub ip: Option<IpAddr>,
+    /// Presented an API token listed in `RATE_LIMIT_EXEMPT_TOKENS`
+    pub exempt: bool,
+}
+
+#[async_trait]
+impl FromRequestParts<AppState> for Client {
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let config = &state.config.rate_limits;
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+
+        let ip = match config.trusted_proxy_hops {
+            0 => peer,
+            hops => forwarded_ip(&parts.headers, hops).or(peer),
+        };
+
+        let exempt = bearer_token(&parts.headers)
+            .is_some_and(|token| config.exempt_tokens.iter().any(|t| t == token));
+
+        Ok(Self { ip, exempt })
+    }
+}
+
+/// Pick the client address out of `X-Forwarded-For`, skipping the entries
+/// appended by our own trusted proxies (counted from the right).
+fn forwarded_ip(headers: &HeaderMap, hops: usize) -> Option<IpAddr> {
+    let forwarded: Vec<&str> = headers
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .collect();
+
+    let index = forwarded.len().checked_sub(hops)?;
+    forwarded[index].parse().ok()
+}
+
+pub fn bearer_token(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
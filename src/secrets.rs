@@ -0,0 +1,195 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+use regex::Regex;
+use serde::Serialize;
+
+use crate::config::{SecretConfig, SecretPolicy};
+
+/// Stop scanning once this many findings have been collected
+const MAX_FINDINGS: usize = 20;
+/// Characters of a match shown unmasked in previews
+const PREVIEW_CHARS: usize = 4;
+
+const ENTROPY_KIND: &str = "High-entropy string";
+
+/// Patterns that are always checked, as `(name, regex)`
+const BUILTIN_PATTERNS: &[(&str, &str)] = &[
+    ("AWS access key ID", r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b"),
+    (
+        "AWS secret access key",
+        r#"(?i)aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?[A-Za-z0-9/+]{40}\b"#,
+    ),
+    ("Private key", r"-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY(?: BLOCK)?-----"),
+    ("JSON Web Token", r"\beyJ[A-Za-z0-9_-]{10,}\.eyJ[A-Za-z0-9_-]{10,}\.[A-Za-z0-9_-]{10,}"),
+    ("GitHub token", r"\b(?:gh[pousr]_[A-Za-z0-9]{36}|github_pat_[A-Za-z0-9_]{50,})"),
+    ("GitLab token", r"\bglpat-[A-Za-z0-9_-]{20}"),
+    ("Slack token", r"\bxox[abeprs]-[A-Za-z0-9-]{10,}"),
+    ("Stripe secret key", r"\b[rs]k_live_[A-Za-z0-9]{20,}"),
+    ("Google API key", r"\bAIza[0-9A-Za-z_-]{35}"),
+    ("OxidePaste API token", r"\boxp_[A-Za-z0-9_-]{40}"),
+];
+
+/// Something in a paste that looks like a credential
+#[derive(Debug, Clone, Serialize)]
+pub struct Finding {
+    /// Name of the pattern that matched
+    pub kind: String,
+    /// 1-based line number
+    pub line: usize,
+    /// The match with all but its first few characters masked
+    pub preview: String,
+}
+
+struct Pattern {
+    name: String,
+    regex: Regex,
+}
+
+/// Looks for API keys, tokens and private keys in paste content
+pub struct SecretScanner {
+    patterns: Vec<Pattern>,
+    /// Candidate tokens for the entropy heuristic
+    tokens: Regex,
+    entropy_threshold: f64,
+}
+
+impl SecretScanner {
+    /// `None` when scanning is turned off
+    pub fn from_config(config: &SecretConfig) -> Result<Option<Self>, String> {
+        if config.policy == SecretPolicy::Off {
+            return Ok(None);
+        }
+
+        let mut patterns = BUILTIN_PATTERNS
+            .iter()
+            .map(|(name, pattern)| Pattern {
+                name: name.to_string(),
+                regex: Regex::new(pattern).expect("built-in secret pattern is valid"),
+            })
+            .collect::<Vec<_>>();
+
+        if let Some(path) = &config.patterns_file {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read SECRET_PATTERNS_FILE `{}`: {}", path, e))?;
+            patterns.extend(parse_patterns(&text).map_err(|e| format!("SECRET_PATTERNS_FILE {}", e))?);
+        }
+
+        let tokens = Regex::new(&format!(r"[A-Za-z0-9+/=_-]{{{},}}", config.entropy_min_length.max(1)))
+            .map_err(|e| format!("SECRET_ENTROPY_MIN_LENGTH is too large: {}", e))?;
+
+        Ok(Some(Self {
+            patterns,
+            tokens,
+            entropy_threshold: config.entropy_threshold,
+        }))
+    }
+
+    pub fn pattern_count(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Every likely secret in `content`, at most one per kind and line
+    pub fn scan(&self, content: &str) -> Vec<Finding> {
+        let mut findings = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (index, line) in content.lines().enumerate() {
+            let mut matched = Vec::new();
+
+            for pattern in &self.patterns {
+                for m in pattern.regex.find_iter(line) {
+                    matched.push(m.range());
+                    if seen.insert((pattern.name.as_str(), index)) {
+                        findings.push(Finding {
+                            kind: pattern.name.clone(),
+                            line: index + 1,
+                            preview: preview(m.as_str()),
+                        });
+                    }
+                }
+            }
+
+            if self.entropy_threshold > 0.0 {
+                for m in self.tokens.find_iter(line) {
+                    // Already reported under a more specific name
+                    if matched.iter().any(|r| r.start < m.end() && m.start() < r.end) {
+                        continue;
+                    }
+                    if looks_random(m.as_str(), self.entropy_threshold) && seen.insert((ENTROPY_KIND, index)) {
+                        findings.push(Finding {
+                            kind: ENTROPY_KIND.to_string(),
+                            line: index + 1,
+                            preview: preview(m.as_str()),
+                        });
+                    }
+                }
+            }
+
+            if findings.len() >= MAX_FINDINGS {
+                findings.truncate(MAX_FINDINGS);
+                break;
+            }
+        }
+
+        findings
+    }
+}
+
+/// Parse `name = regex` lines, skipping blanks and `#` comments
+fn parse_patterns(text: &str) -> Result<Vec<Pattern>, String> {
+    let mut patterns = Vec::new();
+
+    for (index, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let (name, pattern) = line
+            .split_once('=')
+            .map(|(name, pattern)| (name.trim(), pattern.trim()))
+            .filter(|(name, pattern)| !name.is_empty() && !pattern.is_empty())
+            .ok_or_else(|| format!("line {}: expected `name = regex`", index + 1))?;
+        let regex = Regex::new(pattern).map_err(|e| format!("line {}: {}", index + 1, e))?;
+
+        patterns.push(Pattern {
+            name: name.to_string(),
+            regex,
+        });
+    }
+
+    Ok(patterns)
+}
+
+/// Mixed-case alphanumerics with high Shannon entropy. Hex digests, words
+/// and identifiers stay below the threshold or lack a character class.
+fn looks_random(token: &str, threshold: f64) -> bool {
+    let has_upper = token.bytes().any(|b| b.is_ascii_uppercase());
+    let has_lower = token.bytes().any(|b| b.is_ascii_lowercase());
+    let has_digit = token.bytes().any(|b| b.is_ascii_digit());
+
+    has_upper && has_lower && has_digit && shannon_entropy(token) >= threshold
+}
+
+fn shannon_entropy(s: &str) -> f64 {
+    let mut counts = HashMap::new();
+    for b in s.bytes() {
+        *counts.entry(b).or_insert(0usize) += 1;
+    }
+
+    let len = s.len() as f64;
+    counts
+        .values()
+        .map(|&n| {
+            let p = n as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn preview(matched: &str) -> String {
+    let shown: String = matched.chars().take(PREVIEW_CHARS).collect();
+    let hidden = matched.chars().count().saturating_sub(PREVIEW_CHARS).min(12);
+    format!("{}{}", shown, "*".repeat(hidden))
+}
@@ -0,0 +1,64 @@
+use axum::{
+    extract::{Request, State},
+    http::{header, HeaderValue},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::state::AppState;
+use crate::utils::generate_token;
+
+/// Policy for `/:id/raw`: the browser may show the paste but never run or
+/// load anything from it, even if it is sniffed as HTML
+pub const RAW_CSP: &str = "sandbox; default-src 'none'";
+
+tokio::task_local! {
+    static NONCE: String;
+}
+
+/// Nonce that lets an inline `<script>` run on the page being rendered
+pub fn csp_nonce() -> String {
+    NONCE.try_with(String::clone).unwrap_or_default()
+}
+
+/// Adds a Content-Security-Policy and the other security headers to every
+/// response. Handlers that set their own CSP keep it.
+pub async fn security_headers(State(state): State<AppState>, request: Request, next: Next) -> Response {
+    let nonce = generate_token();
+    let mut response = NONCE.scope(nonce.clone(), next.run(request)).await;
+    let config = &state.config.security;
+    let headers = response.headers_mut();
+
+    if !headers.contains_key(header::CONTENT_SECURITY_POLICY) {
+        let policy = format!(
+            "default-src 'self'; \
+             script-src 'nonce-{}'; \
+             style-src 'self'; \
+             font-src 'self'; \
+             img-src 'self' data:; \
+             object-src 'none'; \
+             base-uri 'none'; \
+             form-action 'self'; \
+             frame-ancestors {}",
+            nonce, config.frame_ancestors
+        );
+        if let Ok(value) = HeaderValue::from_str(&policy) {
+            headers.insert(header::CONTENT_SECURITY_POLICY, value);
+        }
+    }
+
+    headers.insert(header::X_CONTENT_TYPE_OPTIONS, HeaderValue::from_static("nosniff"));
+    headers.insert(header::REFERRER_POLICY, HeaderValue::from_static("no-referrer"));
+    if config.frame_ancestors == "'none'" {
+        headers.insert(header::X_FRAME_OPTIONS, HeaderValue::from_static("DENY"));
+    }
+
+    if config.hsts_max_age > 0 && state.config.serves_https() {
+        let value = format!("max-age={}; includeSubDomains", config.hsts_max_age);
+        if let Ok(value) = HeaderValue::from_str(&value) {
+            headers.insert(header::STRICT_TRANSPORT_SECURITY, value);
+        }
+    }
+
+    response
+}
@@ -0,0 +1,247 @@
+use std::convert::Infallible;
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use axum::{extract::Request, response::Response, Router};
+use hyper::{body::Incoming, server::conn::http1};
+use hyper_util::{rt::TokioIo, service::TowerToHyperService};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+use tower_service::Service;
+
+use crate::config::{ListenAddr, ListenConfig};
+
+// =============================================================================
+// Listeners
+// =============================================================================
+
+pub enum Listener {
+    Tcp(TcpListener),
+    /// With the socket path to remove at shutdown, for sockets we created
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, Option<std::path::PathBuf>),
+}
+
+impl Listener {
+    /// Where the listener accepts connections, for the startup log
+    pub fn describe(&self) -> String {
+        match self {
+            Self::Tcp(listener) => match listener.local_addr() {
+                Ok(addr) => addr.to_string(),
+                Err(_) => "TCP socket".to_string(),
+            },
+            #[cfg(unix)]
+            Self::Unix(listener, _) => match listener.local_addr().ok().and_then(|a| a.as_pathname().map(|p| p.to_owned())) {
+                Some(path) => format!("unix:{}", path.display()),
+                None => "Unix socket".to_string(),
+            },
+        }
+    }
+}
+
+/// Open the sockets to serve on: the ones systemd passed in when started by
+/// socket activation, otherwise everything in `LISTEN`
+pub async fn bind(config: &ListenConfig) -> Result<Vec<Listener>, String> {
+    #[cfg(unix)]
+    if let Some(listeners) = systemd::listeners()? {
+        return Ok(listeners);
+    }
+
+    let mut listeners = Vec::new();
+    for addr in &config.addrs {
+        let listener = match addr {
+            ListenAddr::Tcp(addr) => TcpListener::bind(addr)
+                .await
+                .map(Listener::Tcp)
+                .map_err(|e| format!("Failed to listen on {}: {}", addr, e))?,
+            ListenAddr::Unix(path) => bind_unix(path, config.socket_mode)?,
+        };
+        listeners.push(listener);
+    }
+    Ok(listeners)
+}
+
+#[cfg(unix)]
+fn bind_unix(path: &std::path::Path, mode: u32) -> Result<Listener, String> {
+    use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+
+    // A socket file left behind by an earlier run would make bind fail, but
+    // one that still accepts connections belongs to a running instance
+    if std::fs::symlink_metadata(path).is_ok_and(|m| m.file_type().is_socket()) {
+        if std::os::unix::net::UnixStream::connect(path).is_ok() {
+            return Err(format!("{} is in use by another process", path.display()));
+        }
+        std::fs::remove_file(path).map_err(|e| format!("Failed to remove stale socket {}: {}", path.display(), e))?;
+    }
+
+    let listener = tokio::net::UnixListener::bind(path)
+        .map_err(|e| format!("Failed to listen on {}: {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))
+        .map_err(|e| format!("Failed to set permissions on {}: {}", path.display(), e))?;
+    Ok(Listener::Unix(listener, Some(path.to_owned())))
+}
+
+#[cfg(not(unix))]
+fn bind_unix(path: &std::path::Path, _mode: u32) -> Result<Listener, String> {
+    Err(format!("Can't listen on {}: Unix sockets aren't supported on this platform", path.display()))
+}
+
+#[cfg(unix)]
+mod systemd {
+    use std::env;
+    use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+
+    use super::Listener;
+
+    /// First descriptor passed by the service manager (`SD_LISTEN_FDS_START`)
+    const LISTEN_FDS_START: RawFd = 3;
+
+    /// Sockets handed over through `LISTEN_FDS`, if they are meant for us
+    pub fn listeners() -> Result<Option<Vec<Listener>>, String> {
+        let Ok(pid) = env::var("LISTEN_PID") else {
+            return Ok(None);
+        };
+        if pid.trim().parse::<u32>() != Ok(std::process::id()) {
+            return Ok(None);
+        }
+
+        let count: RawFd = env::var("LISTEN_FDS")
+            .unwrap_or_default()
+            .trim()
+            .parse()
+            .map_err(|_| "LISTEN_FDS is not a number".to_string())?;
+        if count <= 0 {
+            return Ok(None);
+        }
+
+        (LISTEN_FDS_START..LISTEN_FDS_START + count).map(adopt).collect::<Result<_, _>>().map(Some)
+    }
+
+    fn adopt(fd: RawFd) -> Result<Listener, String> {
+        let error = |e: std::io::Error| format!("Socket {} from systemd is unusable: {}", fd, e);
+
+        // SAFETY: systemd passes these descriptors to this process only, and
+        // each one is taken over exactly once
+        let unix = unsafe { std::os::unix::net::UnixListener::from_raw_fd(fd) };
+        if unix.local_addr().is_ok() {
+            unix.set_nonblocking(true).map_err(error)?;
+            return tokio::net::UnixListener::from_std(unix)
+                .map(|listener| Listener::Unix(listener, None))
+                .map_err(error);
+        }
+
+        // Not a Unix socket, so it has to be TCP
+        let tcp = unsafe { std::net::TcpListener::from_raw_fd(unix.into_raw_fd()) };
+        tcp.local_addr().map_err(error)?;
+        tcp.set_nonblocking(true).map_err(error)?;
+        tokio::net::TcpListener::from_std(tcp).map(Listener::Tcp).map_err(error)
+    }
+}
+
+// =============================================================================
+// Serving
+// =============================================================================
+
+/// Accept connections on `listener` until `shutdown` is cancelled. TCP
+/// connections go through `tls` when it is set; Unix sockets always speak
+/// plain HTTP, since they are meant for a proxy on the same host.
+///
+/// Connections run on `connections` and finish their current request once
+/// shutdown starts.
+pub async fn serve(
+    listener: Listener,
+    tls: Option<TlsAcceptor>,
+    app: Router,
+    connections: TaskTracker,
+    shutdown: CancellationToken,
+) {
+    match listener {
+        Listener::Tcp(listener) => {
+            let mut make_service = app.into_make_service_with_connect_info::<SocketAddr>();
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = shutdown.cancelled() => return,
+                };
+                let Some((stream, peer)) = accept(accepted).await else {
+                    continue;
+                };
+                let service = match make_service.call(peer).await {
+                    Ok(service) => service,
+                    Err(never) => match never {},
+                };
+
+                let shutdown = shutdown.clone();
+                match tls.clone() {
+                    Some(acceptor) => connections.spawn(async move {
+                        // Handshake failures are the client's problem (scanners,
+                        // expired trust stores) and not worth logging
+                        if let Ok(stream) = acceptor.accept(stream).await {
+                            serve_connection(stream, service, shutdown).await;
+                        }
+                    }),
+                    None => connections.spawn(serve_connection(stream, service, shutdown)),
+                };
+            }
+        }
+        #[cfg(unix)]
+        Listener::Unix(listener, path) => {
+            loop {
+                let accepted = tokio::select! {
+                    accepted = listener.accept() => accepted,
+                    _ = shutdown.cancelled() => break,
+                };
+                // No peer address here; set TRUSTED_PROXY_HOPS so rate limits
+                // can use X-Forwarded-For instead
+                let Some((stream, _)) = accept(accepted).await else {
+                    continue;
+                };
+                connections.spawn(serve_connection(stream, app.clone(), shutdown.clone()));
+            }
+
+            drop(listener);
+            if let Some(path) = path {
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+}
+
+/// Errors about a single connection are skipped; anything else (usually
+/// running out of file descriptors) gets a pause before the next try
+async fn accept<T>(result: io::Result<T>) -> Option<T> {
+    match result {
+        Ok(conn) => Some(conn),
+        Err(e) => {
+            if !matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionAborted | io::ErrorKind::ConnectionReset | io::ErrorKind::ConnectionRefused
+            ) {
+                tracing::error!(error = %e, "Failed to accept connection");
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            None
+        }
+    }
+}
+
+async fn serve_connection<I, S>(io: I, service: S, shutdown: CancellationToken)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    S: Service<Request<Incoming>, Response = Response, Error = Infallible> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+{
+    let conn = http1::Builder::new().serve_connection(TokioIo::new(io), TowerToHyperService::new(service));
+    tokio::pin!(conn);
+
+    tokio::select! {
+        _ = conn.as_mut() => return,
+        _ = shutdown.cancelled() => conn.as_mut().graceful_shutdown(),
+    }
+    // Lets the request in flight finish, then closes the connection
+    let _ = conn.await;
+}
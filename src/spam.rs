@@ -0,0 +1,260 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use regex::{Regex, RegexBuilder};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+
+use crate::config::SpamConfig;
+use crate::utils::{content_hash, generate_token};
+
+/// How long a proof-of-work challenge can be solved and submitted
+const CHALLENGE_TTL: Duration = Duration::from_secs(30 * 60);
+/// The link ratio is only judged once a paste has this many links
+const MIN_LINKS_FOR_RATIO: usize = 3;
+
+// =============================================================================
+// Pipeline
+// =============================================================================
+
+/// One heuristic in the spam pipeline
+pub trait SpamCheck: Send + Sync {
+    /// Why `content` looks like spam, or `None` if it passes
+    fn check(&self, content: &str) -> Option<String>;
+}
+
+/// Spam checks for anonymous pastes, run in order before a paste is stored
+pub struct SpamFilter {
+    checks: Vec<Box<dyn SpamCheck>>,
+    work: Option<ProofOfWork>,
+}
+
+impl SpamFilter {
+    pub fn from_config(config: &SpamConfig) -> Result<Self, String> {
+        let mut checks: Vec<Box<dyn SpamCheck>> = Vec::new();
+
+        if config.max_links > 0 || config.max_link_ratio > 0.0 {
+            checks.push(Box::new(LinkDensity::new(config.max_links, config.max_link_ratio)));
+        }
+
+        let mut terms = config.blocklist.clone();
+        if let Some(path) = &config.blocklist_file {
+            let text = fs::read_to_string(path)
+                .map_err(|e| format!("Failed to read SPAM_BLOCKLIST_FILE `{}`: {}", path, e))?;
+            terms.extend(
+                text.lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(str::to_string),
+            );
+        }
+        if let Some(blocklist) = Blocklist::new(&terms)? {
+            checks.push(Box::new(blocklist));
+        }
+
+        let work = (config.pow_difficulty > 0).then(|| ProofOfWork::new(config.pow_difficulty));
+
+        Ok(Self { checks, work })
+    }
+
+    /// The first reason the content was rejected, if any
+    pub fn check(&self, content: &str) -> Result<(), String> {
+        match self.checks.iter().find_map(|c| c.check(content)) {
+            Some(reason) => Err(reason),
+            None => Ok(()),
+        }
+    }
+
+    /// A fresh proof-of-work challenge, when the challenge is enabled
+    pub fn challenge(&self) -> Option<Challenge> {
+        self.work.as_ref().map(ProofOfWork::issue)
+    }
+
+    /// Check a solved challenge; each one is only accepted once
+    pub fn verify_work(&self, challenge: Option<&str>, nonce: Option<&str>) -> Result<(), &'static str> {
+        match &self.work {
+            Some(work) => work.verify(challenge.unwrap_or(""), nonce.unwrap_or("")),
+            None => Ok(()),
+        }
+    }
+
+    /// Forget challenges that have expired anyway
+    pub fn prune(&self) {
+        if let Some(work) = &self.work {
+            work.prune();
+        }
+    }
+}
+
+// =============================================================================
+// Heuristics
+// =============================================================================
+
+/// Rejects pastes that are mostly links
+struct LinkDensity {
+    links: Regex,
+    max_links: usize,
+    max_ratio: f64,
+}
+
+impl LinkDensity {
+    fn new(max_links: usize, max_ratio: f64) -> Self {
+        Self {
+            links: Regex::new(r#"(?i)\b(?:https?://|www\.)[^\s<>"']+"#).expect("link pattern is valid"),
+            max_links,
+            max_ratio,
+        }
+    }
+}
+
+impl SpamCheck for LinkDensity {
+    fn check(&self, content: &str) -> Option<String> {
+        let links: Vec<_> = self.links.find_iter(content).collect();
+
+        if self.max_links > 0 && links.len() > self.max_links {
+            return Some(format!(
+                "It contains {} links; anonymous pastes can have at most {}.",
+                links.len(),
+                self.max_links
+            ));
+        }
+
+        if self.max_ratio > 0.0 && links.len() >= MIN_LINKS_FOR_RATIO {
+            let link_chars: usize = links.iter().map(|m| m.as_str().chars().count()).sum();
+            let total = content.chars().filter(|c| !c.is_whitespace()).count();
+            if link_chars as f64 > total as f64 * self.max_ratio {
+                return Some("It consists mostly of links.".to_string());
+            }
+        }
+
+        None
+    }
+}
+
+/// Rejects pastes containing any of a list of words or phrases
+struct Blocklist {
+    terms: Regex,
+}
+
+impl Blocklist {
+    /// `None` when the list is empty
+    fn new(terms: &[String]) -> Result<Option<Self>, String> {
+        if terms.is_empty() {
+            return Ok(None);
+        }
+
+        let pattern = terms.iter().map(|t| regex::escape(t)).collect::<Vec<_>>().join("|");
+        let terms = RegexBuilder::new(&pattern)
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| format!("Spam blocklist is too large: {}", e))?;
+
+        Ok(Some(Self { terms }))
+    }
+}
+
+impl SpamCheck for Blocklist {
+    fn check(&self, content: &str) -> Option<String> {
+        self.terms
+            .is_match(content)
+            .then(|| "It contains a blocked word or phrase.".to_string())
+    }
+}
+
+// =============================================================================
+// Proof of Work
+// =============================================================================
+
+/// A puzzle for the browser: find a nonce so that SHA-256 of
+/// `token:nonce` starts with `difficulty` zero bits
+#[derive(Debug, Clone, Serialize)]
+pub struct Challenge {
+    pub token: String,
+    pub difficulty: u32,
+}
+
+/// Hashcash-style challenges, signed so that no state is kept until one is
+/// redeemed
+struct ProofOfWork {
+    difficulty: u32,
+    /// Signing key; challenges from before a restart are no longer accepted
+    secret: String,
+    /// Redeemed challenges, kept until they would have expired
+    used: Mutex<HashMap<String, Instant>>,
+}
+
+impl ProofOfWork {
+    fn new(difficulty: u32) -> Self {
+        Self {
+            difficulty,
+            secret: generate_token(),
+            used: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn sign(&self, body: &str) -> String {
+        content_hash(&format!("{}:{}", self.secret, body))
+    }
+
+    fn issue(&self) -> Challenge {
+        let body = format!("{}.{}.{}", unix_time(), self.difficulty, generate_token());
+        Challenge {
+            token: format!("{}.{}", body, self.sign(&body)),
+            difficulty: self.difficulty,
+        }
+    }
+
+    fn verify(&self, token: &str, nonce: &str) -> Result<(), &'static str> {
+        const MISSING: &str = "The anti-spam check didn't run. Make sure JavaScript is enabled and try again.";
+        const EXPIRED: &str = "The anti-spam check expired. Submit the paste again.";
+
+        let (body, signature) = token.rsplit_once('.').ok_or(MISSING)?;
+        if nonce.is_empty() || self.sign(body) != signature {
+            return Err(MISSING);
+        }
+
+        let mut parts = body.split('.');
+        let issued: u64 = parts.next().and_then(|p| p.parse().ok()).ok_or(MISSING)?;
+        let difficulty: u32 = parts.next().and_then(|p| p.parse().ok()).ok_or(MISSING)?;
+        if unix_time().saturating_sub(issued) > CHALLENGE_TTL.as_secs() {
+            return Err(EXPIRED);
+        }
+
+        let hash = Sha256::digest(format!("{}:{}", token, nonce).as_bytes());
+        if difficulty < self.difficulty || leading_zero_bits(&hash) < difficulty {
+            return Err(MISSING);
+        }
+
+        let mut used = self.used.lock().unwrap();
+        if used.contains_key(token) {
+            return Err(EXPIRED);
+        }
+        used.insert(token.to_string(), Instant::now());
+        Ok(())
+    }
+
+    fn prune(&self) {
+        let now = Instant::now();
+        self.used
+            .lock()
+            .unwrap()
+            .retain(|_, redeemed| now.duration_since(*redeemed) < CHALLENGE_TTL);
+    }
+}
+
+fn leading_zero_bits(hash: &[u8]) -> u32 {
+    let mut bits = 0;
+    for byte in hash {
+        bits += byte.leading_zeros();
+        if *byte != 0 {
+            break;
+        }
+    }
+    bits
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
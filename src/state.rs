@@ -0,0 +1,44 @@
+use axum::extract::FromRef;
+use sqlx::SqlitePool;
+use std::sync::Arc;
+
+use crate::cache::PasteCache;
+use crate::config::Config;
+use crate::mail::Mailer;
+use crate::metrics::Metrics;
+use crate::oidc::Provider;
+use crate::ratelimit::RateLimiter;
+use crate::secrets::SecretScanner;
+use crate::spam::SpamFilter;
+use crate::tasks::Heartbeat;
+
+/// Shared application state handed to every handler
+#[derive(Clone)]
+pub struct AppState {
+    pub pool: SqlitePool,
+    pub config: Arc<Config>,
+    pub limiter: Arc<RateLimiter>,
+    /// Present when an SMTP relay is configured
+    pub mailer: Option<Arc<Mailer>>,
+    /// Present when an OpenID Connect provider is configured
+    pub sso: Option<Arc<Provider>>,
+    /// Present unless `SECRET_POLICY` is `off`
+    pub secrets: Option<Arc<SecretScanner>>,
+    pub spam: Arc<SpamFilter>,
+    pub metrics: Arc<Metrics>,
+    pub pastes: Arc<PasteCache>,
+    /// Beaten by the cleanup task after every run, for `/readyz`
+    pub cleanup: Arc<Heartbeat>,
+}
+
+impl FromRef<AppState> for SqlitePool {
+    fn from_ref(state: &AppState) -> Self {
+        state.pool.clone()
+    }
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
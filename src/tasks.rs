@@ -0,0 +1,155 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tokio_util::sync::CancellationToken;
+use tokio_util::task::TaskTracker;
+
+/// How often expired pastes and stale rate limit entries are cleaned up
+pub const CLEANUP_INTERVAL: Duration = Duration::from_secs(300);
+
+/// How long sign-in attempts on existing accounts are kept for the history on
+/// the settings page. Attempts on unknown usernames only last the lockout window.
+pub const LOGIN_HISTORY: Duration = Duration::from_secs(90 * 86400);
+
+/// Pause before restarting a task that panicked. It doubles with every panic
+/// in a row, up to `MAX_RESTART_DELAY`.
+const RESTART_DELAY: Duration = Duration::from_secs(1);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(60);
+
+// =============================================================================
+// Supervisor
+// =============================================================================
+
+/// Runs long-lived background tasks, starts them again when they panic and
+/// tells them to stop at shutdown
+pub struct Supervisor {
+    shutdown: CancellationToken,
+    tasks: TaskTracker,
+    restarts: Arc<AtomicUsize>,
+}
+
+impl Supervisor {
+    pub fn new(shutdown: CancellationToken) -> Self {
+        Self {
+            shutdown,
+            tasks: TaskTracker::new(),
+            restarts: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Run the future made by `task` until it returns. It gets the shutdown
+    /// token and should return soon after the token is cancelled.
+    pub fn spawn<F, Fut>(&self, name: &'static str, task: F)
+    where
+        F: Fn(CancellationToken) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let shutdown = self.shutdown.clone();
+        let restarts = self.restarts.clone();
+
+        self.tasks.spawn(async move {
+            let mut delay = RESTART_DELAY;
+            loop {
+                let started = Instant::now();
+                let Err(e) = tokio::spawn(task(shutdown.clone())).await else {
+                    return;
+                };
+                if !e.is_panic() || shutdown.is_cancelled() {
+                    return;
+                }
+
+                // A task that ran fine for a while before panicking starts
+                // over with the short delay
+                if started.elapsed() > MAX_RESTART_DELAY {
+                    delay = RESTART_DELAY;
+                }
+                restarts.fetch_add(1, Ordering::Relaxed);
+                tracing::error!(task = name, restart_in_secs = delay.as_secs(), "Background task panicked");
+
+                tokio::select! {
+                    _ = tokio::time::sleep(delay) => {}
+                    _ = shutdown.cancelled() => return,
+                }
+                delay = (delay * 2).min(MAX_RESTART_DELAY);
+            }
+        });
+    }
+
+    /// How many times a task had to be restarted
+    pub fn restarts(&self) -> usize {
+        self.restarts.load(Ordering::Relaxed)
+    }
+
+    /// Wait up to `timeout` for every task to return after shutdown was
+    /// signalled; false if some are still running
+    pub async fn stop(&self, timeout: Duration) -> bool {
+        self.tasks.close();
+        tokio::time::timeout(timeout, self.tasks.wait()).await.is_ok()
+    }
+}
+
+// =============================================================================
+// Heartbeat
+// =============================================================================
+
+/// Lets a periodic task show it is still making progress
+pub struct Heartbeat {
+    last: Mutex<Instant>,
+}
+
+impl Heartbeat {
+    pub fn new() -> Self {
+        Self {
+            last: Mutex::new(Instant::now()),
+        }
+    }
+
+    pub fn beat(&self) {
+        *self.last.lock().unwrap() = Instant::now();
+    }
+
+    /// Time since the last beat, or since creation before the first one
+    pub fn age(&self) -> Duration {
+        self.last.lock().unwrap().elapsed()
+    }
+}
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// =============================================================================
+// Signals
+// =============================================================================
+
+/// Resolves on Ctrl+C (SIGINT) or, on Unix, SIGTERM as sent by `docker stop`
+/// and systemd
+pub async fn shutdown_signal() {
+    let interrupt = async {
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending::<()>().await,
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = interrupt => {}
+        _ = terminate => {}
+    }
+}
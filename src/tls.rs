@@ -0,0 +1,151 @@
+use std::fmt;
+use std::io;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use axum::{
+    http::{uri::PathAndQuery, Uri},
+    response::Redirect,
+    Router,
+};
+use rustls_pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer};
+use tokio::net::TcpListener;
+use tokio_rustls::rustls::{
+    crypto::{ring, CryptoProvider},
+    server::{ClientHello, ResolvesServerCert},
+    sign::CertifiedKey,
+    ServerConfig,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+use crate::config::TlsConfig;
+
+// =============================================================================
+// Certificates
+// =============================================================================
+
+/// Hands out the current certificate, which can be swapped while running
+struct ReloadingCert {
+    provider: Arc<CryptoProvider>,
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl fmt::Debug for ReloadingCert {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReloadingCert").finish_non_exhaustive()
+    }
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap().clone())
+    }
+}
+
+fn load_cert(provider: &CryptoProvider, config: &TlsConfig) -> Result<CertifiedKey, String> {
+    let chain = CertificateDer::pem_file_iter(&config.cert_file)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .map_err(|e| format!("Failed to read TLS_CERT_FILE `{}`: {}", config.cert_file, e))?;
+    if chain.is_empty() {
+        return Err(format!("TLS_CERT_FILE `{}` contains no certificates", config.cert_file));
+    }
+
+    let key = PrivateKeyDer::from_pem_file(&config.key_file)
+        .map_err(|e| format!("Failed to read TLS_KEY_FILE `{}`: {}", config.key_file, e))?;
+    let key = provider
+        .key_provider
+        .load_private_key(key)
+        .map_err(|e| format!("Unsupported private key in TLS_KEY_FILE: {}", e))?;
+
+    let certified = CertifiedKey::new(chain, key);
+    certified
+        .keys_match()
+        .map_err(|e| format!("TLS_CERT_FILE and TLS_KEY_FILE don't belong together: {}", e))?;
+    Ok(certified)
+}
+
+fn modified(config: &TlsConfig) -> Option<(SystemTime, SystemTime)> {
+    let cert = std::fs::metadata(&config.cert_file).and_then(|m| m.modified()).ok()?;
+    let key = std::fs::metadata(&config.key_file).and_then(|m| m.modified()).ok()?;
+    Some((cert, key))
+}
+
+/// Build the TLS acceptor, along with the watcher that swaps in new
+/// certificates for it
+pub fn acceptor(config: &TlsConfig) -> Result<(TlsAcceptor, CertWatcher), String> {
+    let provider = Arc::new(ring::default_provider());
+    let resolver = Arc::new(ReloadingCert {
+        current: RwLock::new(Arc::new(load_cert(&provider, config)?)),
+        provider: provider.clone(),
+    });
+
+    let mut server = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()
+        .map_err(|e| format!("Failed to set up TLS: {}", e))?
+        .with_no_client_auth()
+        .with_cert_resolver(resolver.clone());
+    server.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    let watcher = CertWatcher {
+        resolver,
+        config: config.clone(),
+    };
+    Ok((TlsAcceptor::from(Arc::new(server)), watcher))
+}
+
+/// Checks the certificate files every `reload_interval`. When they change the
+/// new pair is used for new connections; if it can't be loaded the old one
+/// stays in place.
+#[derive(Clone)]
+pub struct CertWatcher {
+    resolver: Arc<ReloadingCert>,
+    config: TlsConfig,
+}
+
+impl CertWatcher {
+    pub async fn run(self, shutdown: CancellationToken) {
+        let config = &self.config;
+        let mut last = modified(config);
+        let mut interval = tokio::time::interval(config.reload_interval);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => {}
+                _ = shutdown.cancelled() => return,
+            }
+            let now = modified(config);
+            if now.is_none() || now == last {
+                continue;
+            }
+            last = now;
+            match load_cert(&self.resolver.provider, config) {
+                Ok(certified) => {
+                    *self.resolver.current.write().unwrap() = Arc::new(certified);
+                    tracing::info!(file = %config.cert_file, "Reloaded TLS certificate");
+                }
+                Err(e) => tracing::warn!(error = %e, "Keeping the current TLS certificate"),
+            }
+        }
+    }
+}
+
+// =============================================================================
+// Redirect
+// =============================================================================
+
+/// Plain HTTP listener that sends every request to the same path on
+/// `public_url`
+pub async fn redirect(listener: TcpListener, public_url: String, shutdown: CancellationToken) -> io::Result<()> {
+    let app = Router::new().fallback(move |uri: Uri| {
+        let public_url = public_url.clone();
+        async move { redirect_target(&public_url, &uri) }
+    });
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown.cancelled_owned())
+        .await
+}
+
+fn redirect_target(public_url: &str, uri: &Uri) -> Redirect {
+    let path = uri.path_and_query().map(PathAndQuery::as_str).unwrap_or("/");
+    Redirect::permanent(&format!("{}{}", public_url, path))
+}
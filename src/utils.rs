@@ -1,14 +1,181 @@
+use argon2::password_hash::{self, rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand::seq::SliceRandom;
+use std::sync::OnceLock;
+
+use crate::config::{IdConfig, IdMode, PasswordConfig};
+
+/// First path segments taken by routes; never usable as paste IDs
+pub const RESERVED_SLUGS: &[&str] = &[
+    "login", "register", "logout", "dashboard", "public", "2fa",
+    "settings", "admin", "api", "static", "metrics", "healthz", "readyz",
+    "forgot-password", "reset-password", "verify-email", "challenge",
+];
+
+const ID_WORDS: &[&str] = &[
+    "amber", "anchor", "apple", "arrow", "aspen", "atlas", "autumn", "badger", "bamboo", "banner",
+    "basil", "beacon", "bear", "birch", "bison", "blaze", "bloom", "breeze", "brook", "cactus",
+    "candle", "canyon", "cedar", "cherry", "cinder", "citrus", "clover", "cobalt", "comet",
+    "copper", "coral", "cosmic", "cotton", "crane", "crater", "creek", "crimson", "crystal",
+    "cypress", "dawn", "delta", "desert", "dolphin", "dove", "dragon", "dune", "eagle", "echo",
+    "ember", "emerald", "falcon", "fern", "fjord", "flame", "flint", "forest", "fossil", "fox",
+    "frost", "galaxy", "garnet", "gecko", "ginger", "glacier", "golden", "granite", "grove",
+    "harbor", "hawk", "hazel", "heron", "hollow", "honey", "horizon", "iris", "island", "ivory",
+    "jade", "jasmine", "jungle", "juniper", "kelp", "kestrel", "koala", "lagoon", "lantern",
+    "lark", "lava", "lemon", "lilac", "lily", "linen", "lotus", "lunar", "lynx", "magnet", "maple",
+    "marble", "meadow", "mercury", "mesa", "meteor", "mint", "mist", "moss", "nebula", "nectar",
+    "needle", "nimbus", "north", "nova", "oak", "oasis", "ocean", "olive", "onyx", "orbit",
+    "orchid", "otter", "owl", "panda", "paper", "pearl", "pebble", "pepper", "phoenix", "pine",
+    "planet", "plum", "polar", "poppy", "prairie", "prism", "pumpkin", "quartz", "quiet", "rain",
+    "raven", "reef", "ridge", "river", "robin", "rocket", "ruby", "rust", "saffron", "sage",
+    "salmon", "sand", "sapphire", "scarlet", "shadow", "shell", "sierra", "silver", "sky", "slate",
+    "snow", "solar", "spark", "spruce", "squid", "star", "stone", "storm", "summit", "sun",
+    "swift", "thistle", "thunder", "tide", "tiger", "timber", "topaz", "tulip", "tundra", "valley",
+    "velvet", "violet", "walnut", "wave", "willow", "winter", "wolf", "zephyr",
+];
+
 /// Generate a random ID for paste URLs
-pub fn generate_id() -> String {
-    nanoid::nanoid!(8)
+pub fn generate_id(config: &IdConfig) -> String {
+    loop {
+        let id = match config.mode {
+            IdMode::Random => {
+                let length = config.length;
+                nanoid::nanoid!(length, &config.alphabet)
+            }
+            IdMode::Words => {
+                let mut rng = rand::thread_rng();
+                (0..config.words)
+                    .map(|_| *ID_WORDS.choose(&mut rng).unwrap_or(&"paste"))
+                    .collect::<Vec<_>>()
+                    .join("-")
+            }
+        };
+        if !is_reserved(&id) {
+            return id;
+        }
+    }
+}
+
+fn is_reserved(id: &str) -> bool {
+    RESERVED_SLUGS.iter().any(|r| r.eq_ignore_ascii_case(id))
+}
+
+/// Check a user-chosen vanity slug
+pub fn validate_slug(slug: &str) -> Result<(), &'static str> {
+    if slug.len() < 3 || slug.len() > 64 {
+        return Err("Custom URL must be between 3 and 64 characters");
+    }
+    if !slug.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Custom URL may only contain letters, digits, '-' and '_'");
+    }
+    if is_reserved(slug) {
+        return Err("That custom URL is reserved");
+    }
+    Ok(())
+}
+
+/// Case-insensitive match against a glob where `*` is any run of characters
+/// and `?` is any single character
+pub fn matches_glob(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // Position of the last `*` and the text position it was tried at
+    let mut star: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((sp, st)) = star {
+            // Let the last `*` swallow one more character
+            p = sp + 1;
+            t = st + 1;
+            star = Some((sp, st + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// SHA-256 hex digest of paste content, used to deduplicate stored bodies
+pub fn content_hash(content: &str) -> String {
+    use sha2::{Digest, Sha256};
+    format!("{:x}", Sha256::digest(content.as_bytes()))
 }
 
-/// Hash a password using bcrypt
-pub fn hash_password(password: &str) -> Result<String, bcrypt::BcryptError> {
-    bcrypt::hash(password, bcrypt::DEFAULT_COST)
+/// Random secret for session cookies and other bearer-style tokens
+pub fn generate_token() -> String {
+    nanoid::nanoid!(32)
 }
 
-/// Verify a password against a hash
+/// Marks personal API tokens so they are easy to recognise if leaked
+pub const API_TOKEN_PREFIX: &str = "oxp_";
+
+/// Random personal API token
+pub fn generate_api_token() -> String {
+    format!("{}{}", API_TOKEN_PREFIX, nanoid::nanoid!(40))
+}
+
+/// SHA-256 hex digest of a token; only the digest is stored
+pub fn hash_token(token: &str) -> String {
+    content_hash(token)
+}
+
+fn argon2(config: &PasswordConfig) -> Result<Argon2<'static>, argon2::Error> {
+    let params = Params::new(config.memory_kib, config.iterations, config.parallelism, None)?;
+    Ok(Argon2::new(Algorithm::Argon2id, Version::V0x13, params))
+}
+
+/// Hash a password with Argon2id, as a PHC string
+pub fn hash_password(password: &str, config: &PasswordConfig) -> Result<String, password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(argon2(config)?.hash_password(password.as_bytes(), &salt)?.to_string())
+}
+
+/// Verify a password against an Argon2 PHC string or a legacy bcrypt hash
 pub fn verify_password(password: &str, hash: &str) -> bool {
-    bcrypt::verify(password, hash).unwrap_or(false)
+    if !hash.starts_with("$argon2") {
+        return bcrypt::verify(password, hash).unwrap_or(false);
+    }
+    // The parameters are read from the hash itself
+    PasswordHash::new(hash)
+        .is_ok_and(|parsed| Argon2::default().verify_password(password.as_bytes(), &parsed).is_ok())
+}
+
+/// A hash of a random password made with the configured settings. Checking
+/// against it when there is no account to check against takes as long as a
+/// real check, so the timing doesn't tell which usernames exist.
+pub fn dummy_hash(config: &PasswordConfig) -> &'static str {
+    static DUMMY: OnceLock<String> = OnceLock::new();
+    DUMMY.get_or_init(|| {
+        let password: String = nanoid::nanoid!(32);
+        hash_password(&password, config).unwrap_or_else(|e| {
+            tracing::error!(error = %e, "Failed to create dummy password hash");
+            String::new()
+        })
+    })
+}
+
+/// Whether a hash is bcrypt or Argon2 with other settings than configured
+pub fn needs_rehash(hash: &str, config: &PasswordConfig) -> bool {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+        return true;
+    };
+    if parsed.algorithm != Algorithm::Argon2id.ident() {
+        return true;
+    }
+    match Params::try_from(&parsed) {
+        Ok(params) => {
+            params.m_cost() != config.memory_kib
+                || params.t_cost() != config.iterations
+                || params.p_cost() != config.parallelism
+        }
+        Err(_) => true,
+    }
 }
\ No newline at end of file